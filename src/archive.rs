@@ -0,0 +1,925 @@
+#[cfg(any(all(feature = "serve-archive", feature = "piz"), feature = "tar-archive"))]
+use std::borrow::Cow;
+use std::io;
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+use std::collections::HashMap;
+#[cfg(any(all(feature = "serve-archive", feature = "piz"), feature = "tar-archive"))]
+use std::sync::Arc;
+
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+use piz::read::FileTree;
+
+use crate::command::watch::SNAPSHOT_DIR;
+use crate::common::is_contained;
+#[cfg(any(all(feature = "serve-archive", feature = "piz"), feature = "tar-archive"))]
+use crate::common::{is_typed_sibling, UNKNOWN_EXT};
+#[cfg(any(all(feature = "serve-archive", feature = "piz"), feature = "tar-archive"))]
+use crate::common::StreamResponseType;
+#[cfg(any(all(feature = "serve-archive", feature = "piz"), feature = "tar-archive"))]
+use crate::conditional::{RangeSource, Validators};
+#[cfg(feature = "tar-archive")]
+use crate::seekable::{Cursor, PlainFile, ReadAt, SeekableReader};
+use crate::utils::read_dir_recursive;
+#[cfg(any(all(feature = "serve-archive", feature = "piz"), feature = "tar-archive"))]
+use crate::volume;
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+use crate::volume::Mapped;
+#[cfg(feature = "tar-archive")]
+use crate::volume::VolumeReader;
+use crate::warc::WarcSource;
+
+/// File inside an archive
+pub(crate) struct Entry {
+	/// path relative to archive root, separated by `/`
+	pub path: String,
+	pub size: u64,
+	pub modified: Option<SystemTime>,
+	/// path of entry this one is a link to, so content can be stored once
+	pub link: Option<String>,
+}
+
+/// Child of a directory inside an archive
+pub(crate) struct Listing {
+	pub name: String,
+	pub is_dir: bool,
+	pub size: u64,
+	pub modified: Option<SystemTime>,
+}
+
+/// Read-only view of archived content regardless of how it was stored
+pub(crate) enum Archive {
+	Dir(PathBuf),
+	/// captures of watched pages up to given timestamp, latest capture of each page win
+	Snapshot(PathBuf, String),
+	#[cfg(all(feature = "serve-archive", feature = "piz"))]
+	Zip(Box<ZipSource>),
+	#[cfg(feature = "tar-archive")]
+	Tar(TarSource),
+	#[cfg(feature = "sqlite")]
+	Sqlite(SqliteSource),
+	Warc(WarcSource),
+}
+
+impl Archive {
+	/// Open archive folder or zip file, `<path>@<timestamp>` open snapshots of archive folder
+	pub(crate) fn open(spec: &str) -> io::Result<Self> {
+		if let Some((path, ts)) = spec.rsplit_once('@') {
+			let path = PathBuf::from(path);
+			if path.join(SNAPSHOT_DIR).is_dir() {
+				return Ok(Self::Snapshot(path, ts.to_string()));
+			}
+		}
+		let path = PathBuf::from(spec);
+		if path.is_dir() {
+			return Ok(Self::Dir(path));
+		}
+		#[cfg(feature = "tar-archive")]
+		if TarSource::is_tar(&path) {
+			return TarSource::new(&path).map(Self::Tar);
+		}
+		#[cfg(feature = "sqlite")]
+		if SqliteSource::is_sqlite(&path) {
+			return SqliteSource::new(&path).map(Self::Sqlite);
+		}
+		if WarcSource::is_warc(&path) {
+			return WarcSource::new(&path).map(Self::Warc);
+		}
+		#[cfg(all(feature = "serve-archive", feature = "piz"))]
+		if path.is_file() {
+			return ZipSource::new(&path).map(|it| Self::Zip(Box::new(it)));
+		}
+		Err(io::Error::new(io::ErrorKind::NotFound, format!("{spec} is not an archive")))
+	}
+
+	/// Every file in archive sorted by path
+	pub(crate) fn entries(&self) -> Vec<Entry> {
+		let mut entries = match self {
+			Archive::Dir(root) => dir_entries(root, root)
+				.into_iter()
+				.filter(|it| !it.path.starts_with(SNAPSHOT_DIR))
+				.collect(),
+			Archive::Snapshot(root, _) => {
+				let mut entries: Vec<Entry> = vec![];
+				for snapshot in self.snapshots(root) {
+					for mut entry in dir_entries(&snapshot, &snapshot) {
+						// target may be replaced by a later snapshot
+						entry.link = None;
+						entries.retain(|it| it.path != entry.path);
+						entries.push(entry);
+					}
+				}
+				entries
+			}
+			#[cfg(all(feature = "serve-archive", feature = "piz"))]
+			Archive::Zip(zip) => zip.entries(),
+			#[cfg(feature = "tar-archive")]
+			Archive::Tar(tar) => tar.entries(),
+			#[cfg(feature = "sqlite")]
+			Archive::Sqlite(db) => db.entries(),
+			Archive::Warc(warc) => warc.entries(),
+		};
+		entries.sort_by(|a, b| a.path.cmp(&b.path));
+		entries
+	}
+
+	pub(crate) fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+		match self {
+			Archive::Dir(root) => std::fs::read(root.join(path)),
+			Archive::Snapshot(root, _) => self.snapshots(root)
+				.into_iter()
+				.rev()
+				.map(|it| it.join(path))
+				.find(|it| it.is_file())
+				.map(std::fs::read)
+				.unwrap_or_else(|| Err(io::ErrorKind::NotFound.into())),
+			#[cfg(all(feature = "serve-archive", feature = "piz"))]
+			Archive::Zip(zip) => zip.read(path),
+			#[cfg(feature = "tar-archive")]
+			Archive::Tar(tar) => tar.read(path),
+			#[cfg(feature = "sqlite")]
+			Archive::Sqlite(db) => db.read(path),
+			Archive::Warc(warc) => warc.read(path),
+		}
+	}
+
+	/// Snapshot folders up to requested timestamp, oldest first
+	fn snapshots(&self, root: &Path) -> Vec<PathBuf> {
+		let Archive::Snapshot(_, ts) = self else { return vec![]; };
+		// `20230102` mean until end of that day
+		let until = format!("{ts:9<14}");
+		let mut snapshots: Vec<PathBuf> = root.join(SNAPSHOT_DIR)
+			.read_dir()
+			.map(|it| it.flatten().map(|it| it.path()).collect())
+			.unwrap_or_default();
+		snapshots.retain(|it| it.file_name().is_some_and(|it| *it.to_string_lossy() <= *until));
+		snapshots.sort();
+		snapshots
+	}
+}
+
+/// Every file under `dir` with path relative to `root`, files reached through symbolic links
+/// and other names of hard linked files are links to the first name of their content
+pub(crate) fn dir_entries(root: &Path, dir: &Path) -> Vec<Entry> {
+	let real_root = root.canonicalize().ok();
+	let mut paths = read_dir_recursive(dir);
+	paths.sort_unstable();
+	#[cfg(unix)]
+	let mut linked = std::collections::HashMap::new();
+	paths.into_iter()
+		.filter(|(_, is_dir)| !is_dir)
+		.filter_map(|(path, _)| {
+			let meta = path.metadata().ok()?;
+			// linked folder, its content is listed under the folder it point to
+			if !meta.is_file() { return None; }
+			let name = path.strip_prefix(root).ok()?.to_string_lossy().replace('\\', "/");
+			let mut link = path.canonicalize().ok()
+				.and_then(|it| Some(it.strip_prefix(real_root.as_ref()?).ok()?.to_string_lossy().replace('\\', "/")))
+				.filter(|it| *it != name);
+			#[cfg(unix)]
+			{
+				use std::os::unix::fs::MetadataExt;
+				if link.is_none() && meta.nlink() > 1 {
+					let target = linked.entry((meta.dev(), meta.ino())).or_insert_with(|| name.clone());
+					link = Some(target.clone()).filter(|it| *it != name);
+				}
+			}
+			Some(Entry {
+				path: name,
+				size: meta.len(),
+				modified: meta.modified().ok(),
+				link,
+			})
+		})
+		.collect()
+}
+
+/// Children of `dir` inside archive folder, `None` if it's not a directory or it's outside of archive
+pub(crate) fn list_dir(root: &Path, dir: &str) -> Option<Vec<Listing>> {
+	if !is_contained(dir) { return None; }
+	let mut list: Vec<Listing> = root.join(dir)
+		.read_dir()
+		.ok()?
+		.flatten()
+		.filter_map(|it| {
+			let meta = it.metadata().ok()?;
+			Some(Listing {
+				name: it.file_name().to_string_lossy().into_owned(),
+				is_dir: meta.is_dir(),
+				size: meta.len(),
+				modified: meta.modified().ok(),
+			})
+		})
+		.collect();
+	list.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+	Some(list)
+}
+
+/// Table that `convert` store archive in
+#[cfg(feature = "sqlite")]
+pub(crate) const SQLITE_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS resource (path TEXT PRIMARY KEY, modified INTEGER, content BLOB NOT NULL)";
+
+/// Archive stored as sqlite database made by `convert`
+#[cfg(feature = "sqlite")]
+pub(crate) struct SqliteSource(rusqlite::Connection);
+
+#[cfg(feature = "sqlite")]
+impl SqliteSource {
+	pub(crate) fn is_sqlite(path: &Path) -> bool {
+		let name = path.to_string_lossy();
+		name.ends_with(".sqlite") || name.ends_with(".db")
+	}
+
+	pub(crate) fn new(path: &Path) -> io::Result<Self> {
+		rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+			.map(Self)
+			.map_err(io::Error::other)
+	}
+
+	pub(crate) fn entries(&self) -> Vec<Entry> {
+		let query = |db: &rusqlite::Connection| -> rusqlite::Result<Vec<Entry>> {
+			let mut stmt = db.prepare("SELECT path, length(content), modified FROM resource")?;
+			let rows = stmt.query_map([], |row| Ok(Entry {
+				path: row.get(0)?,
+				size: row.get(1)?,
+				modified: row.get::<_, Option<u64>>(2)?.map(|it| SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(it)),
+				link: None,
+			}))?;
+			rows.collect()
+		};
+		query(&self.0).unwrap_or_default()
+	}
+
+	pub(crate) fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+		match self.0.query_row("SELECT content FROM resource WHERE path = ?1", [path], |row| row.get(0)) {
+			Err(rusqlite::Error::QueryReturnedNoRows) => Err(io::ErrorKind::NotFound.into()),
+			res => res.map_err(io::Error::other),
+		}
+	}
+}
+
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+#[repr(C)] // prevent field re-order
+pub(crate) struct ZipSource {
+	/// offset of data of stored (uncompressed) entries inside mmap
+	stored: HashMap<String, usize>,
+	/// offset of data of zstd compressed entries, piz can't decompress them
+	zstd: HashMap<String, usize>,
+	/// AES encrypted entries
+	#[cfg(feature = "encrypt")]
+	encrypted: HashMap<String, Encrypted>,
+	/// symbolic link entries and path they point to, compress store aliases this way
+	links: HashMap<String, String>,
+	content: piz::read::DirectoryContents<'static>,
+	zip: piz::ZipArchive<'static>,
+	mmap: Mapped,
+	/// zip file or its volumes, locked while they're mapped
+	files: Vec<std::fs::File>,
+}
+
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+impl ZipSource {
+	/// Open zip file, or volumes of split zip when `path` is their index
+	pub(crate) fn new(path: &std::path::Path) -> std::io::Result<Self> {
+		let files = match volume::archive_name(path) {
+			Some(_) => volume::open_volumes(path)?,
+			None => {
+				let file = std::fs::File::open(path)?;
+				let len = file.metadata()?.len();
+				vec![(file, len)]
+			}
+		};
+		for (file, _) in &files {
+			fs4::FileExt::lock_shared(file)?;
+		}
+		let mmap = Mapped::new(&files)?;
+		let zip = piz::ZipArchive::new(&mmap).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		let zip = unsafe { std::mem::transmute::<piz::ZipArchive<'_>, piz::ZipArchive<'static>>(zip) };
+		let content = piz::read::as_tree(zip.entries()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		let content = unsafe { std::mem::transmute::<piz::read::DirectoryContents<'_>, piz::read::DirectoryContents<'static>>(content) };
+		let (mut stored, mut zstd) = (HashMap::new(), HashMap::new());
+		#[cfg(feature = "encrypt")]
+		let mut encrypted = HashMap::new();
+		for (name, (method, offset, is_encrypted)) in data_offsets(&mmap).unwrap_or_default() {
+			match method {
+				// without passphrase piz refuse to read them
+				_ if is_encrypted => {
+					#[cfg(feature = "encrypt")]
+					encrypted.insert(name, Encrypted { method, offset, key: std::sync::OnceLock::new() });
+				}
+				METHOD_STORED => { stored.insert(name, offset); }
+				METHOD_ZSTD => { zstd.insert(name, offset); }
+				_ => {}
+			}
+		}
+		#[cfg(feature = "encrypt")]
+		if let Some(entry) = zip.entries().iter().filter(|it| it.encrypted).min_by_key(|it| it.compressed_size) {
+			// fail early on wrong passphrase rather than on every request
+			let offset = encrypted.get(entry.path.as_str())
+				.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{}: encrypted entry without local header", entry.path)))?
+				.offset;
+			crate::crypto::check_aes(&mmap[offset..], crate::crypto::passphrase(false)?)?;
+		}
+		let mut links = HashMap::new();
+		for entry in zip.entries().iter().filter(|it| it.unix_mode.is_some_and(|mode| mode & 0o170000 == 0o120000)) {
+			let mut target = String::new();
+			zip.read(entry).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?.read_to_string(&mut target)?;
+			let dir = entry.path.parent().map_or("", |it| it.as_str());
+			links.insert(entry.path.to_string(), resolve_relative(dir, &target));
+		}
+		Ok(Self {
+			stored,
+			zstd,
+			#[cfg(feature = "encrypt")]
+			encrypted,
+			links,
+			content,
+			files: files.into_iter().map(|(file, _)| file).collect(),
+			mmap,
+			zip,
+		})
+	}
+
+	/// Stream `len` bytes of entry starting at `start`, stored entry is sliced directly out of mmap
+	/// and compressed entry is decompressed from beginning and skip until `start`
+	pub(crate) fn get(self: &Arc<Self>, path: &str, start: u64, len: u64) -> Option<StreamResponseType> {
+		let path = self.resolve(path)?;
+		let path = path.as_ref();
+		let entry = self.content.lookup(path).ok()?;
+		if let Some(&offset) = self.stored.get(path) {
+			let start = offset + (start as usize).min(entry.size);
+			let end = start + (len as usize).min(entry.size + offset - start);
+			return Some(Box::pin(futures_util::stream::unfold((Arc::clone(self), start), move |(zip, pos)| async move {
+				if pos >= end { return None; }
+				let next = end.min(pos + (1 << 16));
+				let chunk = bytes::Bytes::copy_from_slice(&zip.mmap[pos..next]);
+				Some((Ok(chunk), (zip, next)))
+			})));
+		}
+		let (content, skip): (Box<dyn Read + Send>, _) = match self.zstd.get(path) {
+			Some(&offset) => {
+				let data = MmapReader { source: Arc::clone(self), pos: offset };
+				(Box::new(zstd::stream::read::Decoder::new(data).ok()?.single_frame().take(entry.size as u64)), start)
+			}
+			#[cfg(feature = "encrypt")]
+			None if self.encrypted.contains_key(path) => {
+				let (method, cipher, key) = self.aes(path, entry).ok()?;
+				// counter mode can start anywhere, only compressed content is read from beginning
+				let pos = if method == METHOD_STORED { start.min(cipher.len() as u64) } else { 0 };
+				let data = MmapReader { source: Arc::clone(self), pos: cipher.start + pos as usize }.take(cipher.len() as u64 - pos);
+				(decompress(method, key.reader(data, pos), entry.size).ok()?, start - pos)
+			}
+			None => (self.zip.read(entry).ok()?, start),
+		};
+		let content = std::sync::Mutex::new(content);
+		// reader borrow from mmap, keep source alive until stream is dropped
+		let state = ZipStream { content, skip, remaining: len, _source: Arc::clone(self) };
+
+		Some(Box::pin(futures_util::stream::unfold(state, |it| async {
+			tokio::task::spawn_blocking(|| {
+				Self::next_entry(it)
+			}).await.unwrap()
+		})))
+	}
+
+	fn next_entry(mut state: ZipStream) -> Option<(crate::common::StreamResponseItem, ZipStream)> {
+		if state.remaining == 0 { return None; }
+		let mut reader = state.content.lock().ok()?;
+		if state.skip > 0 {
+			let skipped = io::copy(&mut (&mut *reader).take(state.skip), &mut io::sink());
+			state.skip = 0;
+			if let Err(err) = skipped {
+				drop(reader);
+				state.remaining = 0;
+				return Some((Err(axum::Error::new(err)), state));
+			}
+		}
+		let mut buf = vec![0; state.remaining.min(1 << 16) as usize];
+		let len = match reader.read(&mut buf) {
+			Ok(0) => {
+				return None;
+			}
+			Ok(n) => {
+				n
+			}
+			Err(err) => {
+				drop(reader);
+				state.remaining = 0;
+				return Some((Err(axum::Error::new(err)), state));
+			}
+		};
+		drop(reader);
+		buf.truncate(len);
+		state.remaining -= len as u64;
+		Some((Ok(buf.into()), state))
+	}
+}
+
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+struct ZipStream {
+	content: std::sync::Mutex<Box<dyn Read + Send>>,
+	skip: u64,
+	remaining: u64,
+	_source: Arc<ZipSource>,
+}
+
+/// Compressed data inside mmap, keep source alive while it's decompressed
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+struct MmapReader {
+	source: Arc<ZipSource>,
+	pos: usize,
+}
+
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+impl Read for MmapReader {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let rest = self.source.mmap.get(self.pos..).unwrap_or_default();
+		let len = rest.len().min(buf.len());
+		buf[..len].copy_from_slice(&rest[..len]);
+		self.pos += len;
+		Ok(len)
+	}
+}
+
+/// Entry of zip that can be served with [`crate::conditional::respond`]
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+pub(crate) struct ZipEntry(pub Arc<ZipSource>, pub String);
+
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+impl RangeSource for ZipEntry {
+	fn range(&self, start: u64, len: u64) -> StreamResponseType {
+		self.0.get(&self.1, start, len).unwrap_or_else(|| Box::pin(futures_util::stream::empty()))
+	}
+}
+
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+impl ZipSource {
+	pub(crate) fn entries(&self) -> Vec<Entry> {
+		self.zip.entries()
+			.iter()
+			.filter(|it| it.is_file())
+			.filter_map(|it| {
+				let path = self.resolve(it.path.as_str())?;
+				let target = self.content.lookup(path.as_ref()).ok().filter(|it| it.is_file())?;
+				Some(Entry {
+					path: it.path.to_string(),
+					size: target.size as u64,
+					modified: Some(target.last_modified.and_utc().into()),
+					link: Some(path.into_owned()).filter(|target| target != it.path.as_str()),
+				})
+			})
+			.collect()
+	}
+
+	/// Path that `path` point to after following links
+	fn resolve<'a>(&'a self, path: &'a str) -> Option<Cow<'a, str>> {
+		follow_links(path, |it| self.links.get(it).map(String::as_str))
+	}
+
+	/// Children of `dir` inside zip, `None` if it's not a directory
+	pub(crate) fn list(&self, dir: &str) -> Option<Vec<Listing>> {
+		let mut list: Vec<Listing> = self.dir(&self.resolve(dir)?)?
+			.iter()
+			.map(|(name, it)| {
+				let meta = it.metadata();
+				let meta = self.resolve(meta.path.as_str()).and_then(|it| self.content.lookup(it.as_ref()).ok()).unwrap_or(meta);
+				Listing {
+					name: name.to_string(),
+					is_dir: meta.is_dir(),
+					size: meta.size as u64,
+					modified: Some(meta.last_modified.and_utc().into()),
+				}
+			})
+			.collect();
+		list.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+		Some(list)
+	}
+
+	/// Validators of file entry, etag is derived from crc and size, or from authentication code of AES entry
+	/// since their crc is left out
+	pub(crate) fn validators(&self, path: &str) -> Option<Validators> {
+		let path = self.resolve(path)?;
+		let entry = self.content.lookup(path.as_ref()).ok()?;
+		#[cfg(feature = "encrypt")]
+		if let Some(it) = self.encrypted.get(path.as_ref()) {
+			let code = self.mmap.get(it.offset + entry.compressed_size.checked_sub(crate::crypto::AES_MAC)?..it.offset + entry.compressed_size)?;
+			return Some(Validators {
+				len: entry.size as u64,
+				modified: Some(entry.last_modified.and_utc().into()),
+				etag: format!("\"{}-{:x}\"", code.iter().map(|it| format!("{it:02x}")).collect::<String>(), entry.size),
+			});
+		}
+		entry.is_file().then(|| Validators {
+			len: entry.size as u64,
+			modified: Some(entry.last_modified.and_utc().into()),
+			etag: format!("\"{:08x}-{:x}\"", entry.crc32, entry.size),
+		})
+	}
+
+	/// Mime type of entry, entry that forward stored as `unknown_ext` take extension from its link target
+	/// or its sibling that has same name and real extension
+	pub(crate) fn content_type(&self, path: &str) -> mime_guess::Mime {
+		let resolved = self.resolve(path);
+		let path = resolved.as_deref().unwrap_or(path);
+		let mut guess = mime_guess::from_path(path).first();
+		if let Some(stem) = path.strip_suffix(UNKNOWN_EXT) {
+			let (dir, name) = stem.rsplit_once('/').unwrap_or(("", stem));
+			let siblings = self.dir(dir);
+			guess = siblings
+				.into_iter()
+				.flat_map(|it| it.keys())
+				.filter(|it| is_typed_sibling(name, it))
+				.find_map(|it| mime_guess::from_path(it).first())
+				.or(guess);
+		}
+		guess.unwrap_or(mime_guess::mime::TEXT_HTML)
+	}
+
+	/// Children of directory in zip tree
+	fn dir(&self, path: &str) -> Option<&piz::read::DirectoryContents<'static>> {
+		let mut current = &self.content;
+		for part in path.trim_end_matches('/').split('/').filter(|it| !it.is_empty()) {
+			match current.get(part)? {
+				piz::read::DirectoryEntry::Directory(dir) => current = &dir.children,
+				piz::read::DirectoryEntry::File(_) => return None,
+			}
+		}
+		Some(current)
+	}
+
+	/// Method, range of cipher text in mmap and key of AES encrypted entry. Key is derived and whole entry
+	/// authenticated on first use, later reads only decrypt what they need
+	#[cfg(feature = "encrypt")]
+	fn aes(&self, path: &str, entry: &piz::read::FileMetadata) -> io::Result<(usize, std::ops::Range<usize>, &crate::crypto::AesKey)> {
+		let it = self.encrypted.get(path).ok_or(io::ErrorKind::NotFound)?;
+		let data = self.mmap.get(it.offset..it.offset + entry.compressed_size).ok_or(io::ErrorKind::UnexpectedEof)?;
+		let key = match it.key.get() {
+			Some(key) => key,
+			None => {
+				let key = crate::crypto::AesKey::open(data, crate::crypto::passphrase(false)?)?;
+				it.key.get_or_init(|| key)
+			}
+		};
+		let cipher = crate::crypto::aes_cipher(data).ok_or(io::ErrorKind::UnexpectedEof)?;
+		Ok((it.method, it.offset + cipher.start..it.offset + cipher.end, key))
+	}
+
+	pub(crate) fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+		let path = self.resolve(path).ok_or(io::ErrorKind::NotFound)?;
+		let path = path.as_ref();
+		let entry = self.content.lookup(path).map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))?;
+		let mut reader: Box<dyn Read + Send> = match self.zstd.get(path) {
+			Some(&offset) => Box::new(zstd::stream::read::Decoder::with_buffer(&self.mmap[offset..])?.single_frame().take(entry.size as u64)),
+			#[cfg(feature = "encrypt")]
+			None if self.encrypted.contains_key(path) => {
+				let (method, cipher, key) = self.aes(path, entry)?;
+				decompress(method, key.reader(&self.mmap[cipher], 0), entry.size)?
+			}
+			None => self.zip.read(entry).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+		};
+		let mut buf = Vec::with_capacity(entry.size);
+		reader.read_to_end(&mut buf)?;
+		Ok(buf)
+	}
+}
+
+/// AES encrypted entry of zip
+#[cfg(all(feature = "serve-archive", feature = "piz", feature = "encrypt"))]
+struct Encrypted {
+	/// how content was compressed before encryption
+	method: usize,
+	/// offset of salt that begin its data
+	offset: usize,
+	/// set once passphrase and authentication code are checked
+	key: std::sync::OnceLock<crate::crypto::AesKey>,
+}
+
+/// Decompress content of entry that was compressed with `method` into `size` bytes
+#[cfg(all(feature = "serve-archive", feature = "piz", feature = "encrypt"))]
+fn decompress<'a>(method: usize, data: impl Read + Send + 'a, size: usize) -> io::Result<Box<dyn Read + Send + 'a>> {
+	Ok(match method {
+		METHOD_STORED => Box::new(data.take(size as u64)),
+		METHOD_ZSTD => Box::new(zstd::stream::read::Decoder::new(data)?.single_frame().take(size as u64)),
+		_ => Box::new(flate2::read::DeflateDecoder::new(data).take(size as u64)),
+	})
+}
+
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+const METHOD_STORED: usize = 0;
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+const METHOD_ZSTD: usize = 93;
+/// WinZip AES encryption, actual method is in extra field
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+const METHOD_AES: usize = 99;
+
+/// Find method, where data begin and whether it's encrypted of every stored, zstd or AES encrypted entry
+/// by walking central directory, piz doesn't expose offset of local headers
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+fn data_offsets(buf: &[u8]) -> Option<HashMap<String, (usize, usize, bool)>> {
+	let u16_at = |pos: usize| buf.get(pos..pos + 2).map(|it| u16::from_le_bytes([it[0], it[1]]) as usize);
+	let u32_at = |pos: usize| buf.get(pos..pos + 4).map(|it| u32::from_le_bytes(it.try_into().unwrap()) as usize);
+	let u64_at = |pos: usize| buf.get(pos..pos + 8).map(|it| u64::from_le_bytes(it.try_into().unwrap()) as usize);
+
+	let search_from = buf.len().saturating_sub(22 + u16::MAX as usize);
+	let eocd = (search_from..buf.len().saturating_sub(21)).rev().find(|&it| u32_at(it) == Some(0x06054b50))?;
+	let (mut entries, mut cd) = (u16_at(eocd + 10)?, u32_at(eocd + 16)?);
+	if cd == u32::MAX as usize || entries == u16::MAX as usize {
+		// zip64 end of central directory locator is right before end of central directory
+		let locator = eocd.checked_sub(20)?;
+		if u32_at(locator)? != 0x07064b50 { return None; }
+		let record = u64_at(locator + 8)?;
+		entries = u64_at(record + 32)?;
+		cd = u64_at(record + 48)?;
+	}
+
+	let mut offsets = HashMap::new();
+	let mut pos = cd;
+	for _ in 0..entries {
+		if u32_at(pos)? != 0x02014b50 { return None; }
+		let encrypted = u16_at(pos + 8)? & 1 != 0;
+		let mut method = u16_at(pos + 10)?;
+		let (name_len, extra_len, comment_len) = (u16_at(pos + 28)?, u16_at(pos + 30)?, u16_at(pos + 32)?);
+		let mut offset = u32_at(pos + 42)?;
+		let name = String::from_utf8_lossy(buf.get(pos + 46..pos + 46 + name_len)?);
+		let mut extra = pos + 46 + name_len;
+		let extra_end = extra + extra_len;
+		while extra + 4 <= extra_end {
+			let (id, size) = (u16_at(extra)?, u16_at(extra + 2)?);
+			if id == 1 && offset == u32::MAX as usize {
+				// zip64 extended information, offset come after sizes that overflow
+				let mut field = extra + 4;
+				for size_pos in [24, 20] {
+					if u32_at(pos + size_pos)? == u32::MAX as usize { field += 8; }
+				}
+				offset = u64_at(field)?;
+			}
+			if id == 0x9901 && method == METHOD_AES {
+				method = u16_at(extra + 9)?;
+			}
+			extra += 4 + size;
+		}
+		if (encrypted || matches!(method, METHOD_STORED | METHOD_ZSTD)) && !name.ends_with('/') {
+			if u32_at(offset)? != 0x04034b50 { return None; }
+			let data = offset + 30 + u16_at(offset + 26)? + u16_at(offset + 28)?;
+			offsets.insert(name.into_owned(), (method, data, encrypted));
+		}
+		pos += 46 + name_len + extra_len + comment_len;
+	}
+	Some(offsets)
+}
+
+/// Entry of tar archive
+#[cfg(feature = "tar-archive")]
+struct TarEntry {
+	/// offset of content in uncompressed tar
+	offset: u64,
+	size: u64,
+	modified: Option<SystemTime>,
+	is_dir: bool,
+	/// archive path of hard link or symbolic link target
+	target: Option<String>,
+}
+
+/// Random access view of plain tar or seekable zstd compressed tar
+#[cfg(feature = "tar-archive")]
+pub(crate) struct TarSource {
+	data: Box<dyn ReadAt>,
+	entries: std::collections::BTreeMap<String, TarEntry>,
+}
+
+#[cfg(feature = "tar-archive")]
+impl TarSource {
+	/// Path look like tar that can be accessed randomly, age encrypted ones end with `.age`
+	/// and split ones are opened by their index
+	pub(crate) fn is_tar(path: &Path) -> bool {
+		let name = volume::archive_name(path).unwrap_or_else(|| path.to_string_lossy().into_owned());
+		#[cfg(feature = "encrypt")]
+		let name = name.strip_suffix(".age").unwrap_or(&name);
+		name.ends_with(".tar") || name.ends_with(".tar.zst")
+	}
+
+	pub(crate) fn new(path: &Path) -> io::Result<Self> {
+		match volume::archive_name(path) {
+			Some(name) => Self::with_reader(&name, VolumeReader::open(path)?),
+			None => Self::with_reader(&path.to_string_lossy(), std::fs::File::open(path)?),
+		}
+	}
+
+	/// Index tar named `name` inside `reader`, it's decrypted when name end with `.age`
+	fn with_reader<R: io::Read + io::Seek + Send + 'static>(name: &str, reader: R) -> io::Result<Self> {
+		#[cfg(feature = "encrypt")]
+		if let Some(name) = name.strip_suffix(".age") {
+			// age is made of 64 KiB chunks that are decrypted on their own, so it can be seeked too
+			let file = PlainFile::new(crate::crypto::age_reader(reader)?)?;
+			return Self::with_data(name, Box::new(file));
+		}
+		Self::with_data(name, Box::new(PlainFile::new(reader)?))
+	}
+
+	/// Index tar inside `file`, that is seekable zstd when `name` end with `.zst`
+	fn with_data(name: &str, file: Box<dyn ReadAt>) -> io::Result<Self> {
+		let data: Box<dyn ReadAt> = if name.ends_with(".zst") {
+			Box::new(SeekableReader::new(file).map_err(|e| io::Error::new(e.kind(), format!("{e}, only seekable zstd (`compress -f seekable-zst`) can be served")))?)
+		} else {
+			file
+		};
+		let mut entries = std::collections::BTreeMap::new();
+		let mut archive = tar::Archive::new(Cursor::new(&*data));
+		// content is skipped by seeking so only frames that contain headers are decompressed
+		for entry in archive.entries_with_seek()? {
+			let entry = entry?;
+			let header = entry.header();
+			let path = entry.path()?.to_string_lossy().trim_start_matches("./").trim_end_matches('/').to_string();
+			if path.is_empty() { continue; }
+			let typ = header.entry_type();
+			let target = match typ {
+				tar::EntryType::Link => entry.link_name()?.map(|it| it.to_string_lossy().trim_start_matches("./").to_string()),
+				tar::EntryType::Symlink => entry.link_name()?.map(|it| {
+					let dir = path.rsplit_once('/').map_or("", |it| it.0);
+					resolve_relative(dir, &it.to_string_lossy())
+				}),
+				_ => None,
+			};
+			entries.insert(path, TarEntry {
+				offset: entry.raw_file_position(),
+				size: entry.size(),
+				modified: header.mtime().ok().map(|it| SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(it)),
+				is_dir: typ.is_dir(),
+				target,
+			});
+		}
+		Ok(Self { data, entries })
+	}
+
+	/// Entry that hold content of `path`, links are followed
+	fn resolve(&self, path: &str) -> Option<&TarEntry> {
+		self.entries.get(self.target(path)?.as_ref())
+	}
+
+	/// Path that `path` point to after following links
+	fn target<'a>(&'a self, path: &'a str) -> Option<Cow<'a, str>> {
+		follow_links(path, |it| self.entries.get(it)?.target.as_deref())
+	}
+
+	pub(crate) fn entries(&self) -> Vec<Entry> {
+		self.entries.keys()
+			.filter_map(|path| {
+				let target = self.target(path)?;
+				let entry = self.entries.get(target.as_ref()).filter(|it| !it.is_dir)?;
+				Some(Entry {
+					path: path.clone(),
+					size: entry.size,
+					modified: entry.modified,
+					link: Some(target.into_owned()).filter(|it| it != path),
+				})
+			})
+			.collect()
+	}
+
+	/// Children of `dir` inside tar, `None` if it's not a directory
+	pub(crate) fn list(&self, dir: &str) -> Option<Vec<Listing>> {
+		let dir = self.target(dir.trim_matches('/'))?;
+		let dir = dir.as_ref();
+		let prefix = if dir.is_empty() { String::new() } else { format!("{dir}/") };
+		if !dir.is_empty() && !self.resolve(dir).map_or_else(|| self.entries.range(prefix.clone()..).next().is_some_and(|(it, _)| it.starts_with(&prefix)), |it| it.is_dir) {
+			return None;
+		}
+		let mut list: Vec<Listing> = vec![];
+		for (path, _) in self.entries.range(prefix.clone()..).take_while(|(it, _)| it.starts_with(&prefix)) {
+			let rest = &path[prefix.len()..];
+			let (name, nested) = match rest.split_once('/') {
+				Some((name, _)) => (name, true),
+				None => (rest, false),
+			};
+			if list.last().is_some_and(|it| it.name == name) { continue; }
+			let entry = self.resolve(path);
+			list.push(Listing {
+				name: name.to_string(),
+				is_dir: nested || entry.is_some_and(|it| it.is_dir),
+				size: entry.map_or(0, |it| it.size),
+				modified: entry.and_then(|it| it.modified),
+			});
+		}
+		list.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+		Some(list)
+	}
+
+	/// Validators of file entry, etag is derived from position and size
+	pub(crate) fn validators(&self, path: &str) -> Option<Validators> {
+		let entry = self.resolve(path).filter(|it| !it.is_dir)?;
+		let secs = entry.modified.and_then(|it| it.duration_since(SystemTime::UNIX_EPOCH).ok()).unwrap_or_default().as_secs();
+		Some(Validators {
+			len: entry.size,
+			modified: entry.modified,
+			etag: format!("\"{:x}-{:x}-{secs:x}\"", entry.offset, entry.size),
+		})
+	}
+
+	/// Mime type of entry, name of link target is preferred since forward link `unknown_ext` to real extension
+	pub(crate) fn content_type(&self, path: &str) -> mime_guess::Mime {
+		let target = self.target(path);
+		let path = target.as_deref().unwrap_or(path);
+		let mut guess = mime_guess::from_path(path).first();
+		if let Some(stem) = path.strip_suffix(UNKNOWN_EXT) {
+			guess = self.entries.range(stem.to_string()..)
+				.take_while(|(it, _)| it.starts_with(stem))
+				.filter(|(it, _)| is_typed_sibling(stem, it))
+				.find_map(|(it, _)| mime_guess::from_path(it).first())
+				.or(guess);
+		}
+		guess.unwrap_or(mime_guess::mime::TEXT_HTML)
+	}
+
+	pub(crate) fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+		let entry = self.resolve(path).filter(|it| !it.is_dir).ok_or(io::ErrorKind::NotFound)?;
+		self.data.read_at(entry.offset, entry.size as usize)
+	}
+
+	/// Stream `len` bytes of entry starting at `start`
+	fn get(self: &Arc<Self>, path: &str, start: u64, len: u64) -> Option<StreamResponseType> {
+		let entry = self.resolve(path)?;
+		let start = entry.offset + start.min(entry.size);
+		let end = start + len.min(entry.offset + entry.size - start);
+		Some(Box::pin(futures_util::stream::unfold((Arc::clone(self), start), move |(tar, pos)| async move {
+			if pos >= end { return None; }
+			let len = (end - pos).min(1 << 16) as usize;
+			let (tar, chunk) = tokio::task::spawn_blocking(move || {
+				let chunk = tar.data.read_at(pos, len);
+				(tar, chunk)
+			}).await.unwrap();
+			match chunk {
+				Ok(chunk) if !chunk.is_empty() => {
+					let next = pos + chunk.len() as u64;
+					Some((Ok(chunk.into()), (tar, next)))
+				}
+				Ok(_) => None,
+				Err(err) => Some((Err(axum::Error::new(err)), (tar, end))),
+			}
+		})))
+	}
+}
+
+/// Entry of tar that can be served with [`crate::conditional::respond`]
+#[cfg(feature = "tar-archive")]
+pub(crate) struct TarFile(pub Arc<TarSource>, pub String);
+
+#[cfg(feature = "tar-archive")]
+impl RangeSource for TarFile {
+	fn range(&self, start: u64, len: u64) -> StreamResponseType {
+		self.0.get(&self.1, start, len).unwrap_or_else(|| Box::pin(futures_util::stream::empty()))
+	}
+}
+
+/// Replace leading part of `path` that is a link with its `target`, `None` if links form a loop
+#[cfg(any(all(feature = "serve-archive", feature = "piz"), feature = "tar-archive"))]
+fn follow_links<'a>(path: &'a str, target: impl Fn(&str) -> Option<&'a str>) -> Option<Cow<'a, str>> {
+	let mut path = Cow::Borrowed(path.trim_matches('/'));
+	'hop: for _ in 0..8 {
+		let ends = path.match_indices('/').map(|(it, _)| it).chain([path.len()]);
+		for end in ends {
+			if let Some(target) = target(&path[..end]) {
+				path = Cow::Owned(format!("{target}{}", &path[end..]));
+				continue 'hop;
+			}
+		}
+		return Some(path);
+	}
+	None
+}
+
+/// Join relative symbolic link `target` with folder `dir` it's located in
+#[cfg(any(all(feature = "serve-archive", feature = "piz"), feature = "tar-archive"))]
+fn resolve_relative(dir: &str, target: &str) -> String {
+	let mut parts: Vec<&str> = if target.starts_with('/') { vec![] } else { dir.split('/').filter(|it| !it.is_empty()).collect() };
+	for part in target.split('/') {
+		match part {
+			"" | "." => {}
+			".." => { parts.pop(); }
+			part => parts.push(part),
+		}
+	}
+	parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn list_outside_of_archive() {
+		let root = std::env::temp_dir().join(format!("archive-it-list-{}", std::process::id()));
+		std::fs::create_dir_all(root.join("docs")).unwrap();
+		std::fs::write(root.join("docs/page.html"), "page").unwrap();
+		let names = |dir| list_dir(&root, dir).map(|it| it.into_iter().map(|it| it.name).collect::<Vec<_>>());
+		assert_eq!(names(""), Some(vec!["docs".to_string()]));
+		assert_eq!(names("docs"), Some(vec!["page.html".to_string()]));
+		assert_eq!(names(".."), None);
+		assert_eq!(names("docs/../../.."), None);
+		assert_eq!(names("/etc"), None);
+		std::fs::remove_dir_all(&root).unwrap();
+	}
+}
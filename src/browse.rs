@@ -0,0 +1,148 @@
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local};
+
+use crate::archive::{Entry, Listing};
+use crate::common::archived_url;
+use crate::search::SearchResult;
+
+/// Url prefix that archive index is served at
+pub(crate) static BROWSE_PREFIX: &str = "_archive";
+
+struct Row {
+	label: String,
+	href: String,
+	is_dir: bool,
+	size: u64,
+	modified: Option<SystemTime>,
+}
+
+/// Index page of directory `dir` of an archive mounted at `base`
+pub(crate) fn render_dir(title: &str, base: &str, dir: &str, list: Vec<Listing>) -> String {
+	let prefix = if dir.is_empty() { String::new() } else { format!("{}/", dir.trim_end_matches('/')) };
+	let mut rows = vec![];
+	if !dir.is_empty() {
+		let parent = prefix.trim_end_matches('/').rsplit_once('/').map_or("", |it| it.0);
+		rows.push(Row { label: String::from(".."), href: format!("{base}/{BROWSE_PREFIX}/{parent}"), is_dir: true, size: 0, modified: None });
+	}
+	rows.extend(list.into_iter().map(|it| Row {
+		href: if it.is_dir {
+			format!("{base}/{BROWSE_PREFIX}/{prefix}{}", it.name)
+		} else {
+			base.to_string() + &archived_url(&format!("{prefix}{}", it.name))
+		},
+		label: if it.is_dir { format!("{}/", it.name) } else { it.name },
+		is_dir: it.is_dir,
+		size: it.size,
+		modified: it.modified,
+	}));
+	page(title, base, dir, "", rows)
+}
+
+/// Every resource under `dir` that contain `query` in its path
+pub(crate) fn render_search(title: &str, base: &str, dir: &str, query: &str, entries: Vec<Entry>) -> String {
+	let needle = query.to_lowercase();
+	let rows = entries.into_iter()
+		.filter(|it| it.path.starts_with(dir) && it.path.to_lowercase().contains(&needle))
+		.map(|it| Row {
+			href: base.to_string() + &archived_url(&it.path),
+			label: it.path,
+			is_dir: false,
+			size: it.size,
+			modified: it.modified,
+		})
+		.collect();
+	page(title, base, dir, query, rows)
+}
+
+static NOT_FOUND: &str = r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>Not archived</title>
+<style>body{font-family:sans-serif;margin:2em}code{background:#eee;padding:.1em .3em}</style></head><body>
+<h1>Not archived</h1>
+<p><code>{url}</code> is not part of this archive (looked for <code>{key}</code>).</p>
+<p>Similar archived resources:</p>
+<ul>{neighbours}</ul>
+</body></html>"#;
+
+/// Page for resource that isn't archived, `template` default to built-in page
+pub(crate) fn render_not_found(template: Option<&str>, url: &str, key: &str, neighbours: &[(String, String)]) -> String {
+	let mut list = String::new();
+	for (href, label) in neighbours {
+		let _ = write!(list, r#"<li><a href="{}">{}</a></li>"#, escape(href), escape(label));
+	}
+	template.unwrap_or(NOT_FOUND)
+		.replace("{url}", &escape(url))
+		.replace("{key}", &escape(key))
+		.replace("{neighbours}", &list)
+}
+
+/// Full-text search result page
+pub(crate) fn render_results(title: &str, base: &str, query: &str, results: Vec<SearchResult<'_>>) -> String {
+	let mut out = String::new();
+	let _ = write!(out, r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>{title} - search</title>
+<style>
+body{{font-family:sans-serif;margin:2em;max-width:60em}}input{{padding:.3em;width:30em}}
+.r{{margin:1.2em 0}}.r a{{font-size:1.1em}}.p{{color:#070;font-size:.9em}}
+</style></head><body>
+<h1>{title}</h1>
+<form action="{base}/{BROWSE_PREFIX}/search"><input name="q" value="{query}" placeholder="search archived pages" autofocus></form>
+<p>{count} results, <a href="{base}/{BROWSE_PREFIX}/">browse archive</a></p>
+"#, title = escape(title), base = escape(base), query = escape(query), count = results.len());
+	for it in results {
+		let _ = writeln!(out, r#"<div class="r"><a href="{}">{}</a><div class="p">{}</div><div>{}</div></div>"#,
+		                 escape(&(base.to_string() + &archived_url(it.path))), escape(it.title), escape(it.path), escape(it.excerpt));
+	}
+	out.push_str("</body></html>");
+	out
+}
+
+fn page(title: &str, base: &str, dir: &str, query: &str, rows: Vec<Row>) -> String {
+	let mut out = String::new();
+	let location = format!("{base}/{BROWSE_PREFIX}/{dir}");
+	let _ = write!(out, r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>{title} - /{dir}</title>
+<style>
+body{{font-family:sans-serif;margin:2em}}table{{border-collapse:collapse;width:100%}}
+td,th{{padding:.2em .8em;text-align:left;border-bottom:1px solid #ddd}}td.n{{text-align:right}}
+input{{padding:.3em;width:20em}}
+</style></head><body>
+<h1>{title}</h1><h2>/{dir}</h2>
+<form action="{location}"><input id="filter" name="q" value="{query}" placeholder="filter (enter to search whole directory)" autofocus></form>
+<table><thead><tr><th>Name</th><th>Size</th><th>Captured</th></tr></thead><tbody>
+"#, title = escape(title), dir = escape(dir), location = escape(&location), query = escape(query));
+	for row in &rows {
+		let size = if row.is_dir { String::new() } else { human_size(row.size) };
+		let modified = row.modified
+			.map(|it| DateTime::<Local>::from(it).format("%Y-%m-%d %H:%M").to_string())
+			.unwrap_or_default();
+		let _ = writeln!(out, r#"<tr><td><a href="{}">{}</a></td><td class="n">{size}</td><td>{modified}</td></tr>"#, escape(&row.href), escape(&row.label));
+	}
+	let _ = write!(out, r#"</tbody></table><p>{} entries, <a href="{base}/{BROWSE_PREFIX}/search">search archived pages</a></p>
+<script>
+const filter = document.getElementById("filter");
+filter.addEventListener("input", () => {{
+	const q = filter.value.toLowerCase();
+	for (const row of document.querySelectorAll("tbody tr")) {{
+		row.style.display = row.textContent.toLowerCase().includes(q) ? "" : "none";
+	}}
+}});
+</script></body></html>"#, rows.len(), base = escape(base));
+	out
+}
+
+fn human_size(size: u64) -> String {
+	let mut size = size as f64;
+	for unit in ["B", "KiB", "MiB", "GiB"] {
+		if size < 1024.0 {
+			return if unit == "B" { format!("{size} {unit}") } else { format!("{size:.1} {unit}") };
+		}
+		size /= 1024.0;
+	}
+	format!("{size:.1} TiB")
+}
+
+pub(crate) fn escape(s: &str) -> String {
+	s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -1,10 +1,10 @@
-#[cfg(feature = "zip")]
-use std::fmt::{Debug, Display, Formatter};
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
-use clap::{Args, Parser};
-#[cfg(feature = "zip")]
-use clap::ValueEnum;
+use clap::{ArgAction, Args, Parser, ValueEnum};
 
 #[derive(Parser)]
 #[command(version, long_about = None)]
@@ -24,56 +24,488 @@ pub(crate) enum Config {
 		/// provide value to replace upstream host with
 		#[arg(short, long)]
 		prefix_local: Option<String>,
+		/// capture every subresource referenced by html/css (srcset, lazy images, fonts...) in background
+		#[arg(long)]
+		eager: bool,
+		/// refresh archived resource in background once it older than ttl, format is `<pattern>=<ttl>`
+		/// (eg. `*.html=1d`, `static/*=never`), first matching pattern win
+		#[arg(long, value_name = "PATTERN=TTL")]
+		ttl: Vec<TtlRule>,
 		// replace url that start with / to relative path
 		//#[arg(short, long)]
 		//rewrite_prefix: bool,
 	},
 	/// Serve local content without forwarding to upstream
 	Serve {
-		/// Path to archive folder
-		path: String,
+		/// Path to archive folder or zip, served for requests that no `--map` match.
+		/// Several comma separated paths are stacked as layers, first one that contain a resource serve it
+		/// (eg. `overrides,delta,base.zip`)
+		#[arg(required_unless_present = "map")]
+		path: Option<String>,
 		#[clap(flatten)]
 		http: HttpConfig,
+		/// Html template of page shown for resource that isn't archived,
+		/// `{url}`, `{key}` and `{neighbours}` will be replaced with requested url, storage key and similar resources
+		#[arg(long)]
+		not_found: Option<PathBuf>,
+		/// Append url of every resource that isn't archived into this file, archives of `--map` get their own file
+		/// with name of host or prefix before extension (eg. `missing.docs.rs.localhost.txt`)
+		#[arg(long)]
+		miss_log: Option<PathBuf>,
+		/// How to pick archived variant when query doesn't match exactly, tried in given order
+		#[arg(short, long = "match", value_delimiter = ',')]
+		matching: Vec<MatchStrategy>,
+		/// Query params (wildcard allowed, eg. `utm_*`) to disregard with `--match ignore-params`
+		#[arg(long, value_name = "PARAM")]
+		ignore_param: Vec<String>,
+		/// Serve another archive for a host or url prefix, format is `<host>=<path>` or `/<prefix>/=<path>`
+		/// (eg. `docs.rs.localhost=docs.zip`, `/mirror/tokio/=tokio-archive`)
+		#[arg(long, value_name = "HOST|PREFIX=PATH")]
+		map: Vec<Mount>,
+		/// Url that archive is reachable at behind reverse proxy (eg. `https://intranet/mirrors/docs.rs/`),
+		/// links of html/css/js are rewritten to it and its path is stripped from incoming requests
+		#[arg(long)]
+		public_url: Option<String>,
+		/// File whose first line is passphrase of encrypted archives (default is `ARCHIVE_IT_KEY` or prompt)
+		#[cfg(feature = "encrypt")]
+		#[arg(long)]
+		key_file: Option<PathBuf>,
 	},
-	#[cfg(feature = "zip")]
+	#[cfg(any(feature = "zip", feature = "tar-archive"))]
 	/// Compress content into single file
 	Compress {
 		/// Path to archive folder
 		path: String,
 		/// Compress format
-		#[arg(short, long, default_value_t = CompressFormat::zip)]
+		#[arg(short, long, default_value_t = CompressFormat::default())]
 		format: CompressFormat,
 		/// Output file
 		output: Option<String>,
+		/// Number of threads that compress in parallel (default is number of cpu)
+		#[arg(short, long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+		jobs: Option<usize>,
+		/// Same folder always give same bytes, entries get fixed timestamp and permissions
+		#[arg(long)]
+		reproducible: bool,
+		/// Timestamp of entries in reproducible mode, unix seconds or `capture` to keep modification time of archived files
+		/// (default is `SOURCE_DATE_EPOCH` or 1980-01-01)
+		#[arg(long, requires = "reproducible", value_name = "SECONDS|capture")]
+		mtime: Option<EntryTime>,
+		/// Compression method of zip entries whose path or mime type match wildcard pattern, first match win
+		/// (eg. `image/*=store`, `*.svg=deflate`, `text/*=zstd`), media that is already compressed is stored by default
+		#[cfg(feature = "zip")]
+		#[arg(long, value_name = "PATTERN=METHOD")]
+		method: Vec<MethodRule>,
+		/// Update existing zip output, only files whose size and modification time or content changed are compressed again,
+		/// others are copied from it as they are and deleted files are dropped
+		#[cfg(feature = "zip")]
+		#[arg(short, long)]
+		update: bool,
+		/// Encrypt with passphrase, zip entries with AES-256 and tar formats as a whole with age (`.age` is appended).
+		/// Names of zip entries stay readable
+		#[cfg(feature = "encrypt")]
+		#[arg(long)]
+		encrypt: bool,
+		/// File whose first line is passphrase (default is `ARCHIVE_IT_KEY` or prompt)
+		#[cfg(feature = "encrypt")]
+		#[arg(long, requires = "encrypt")]
+		key_file: Option<PathBuf>,
+		/// Split output into volumes of this size (eg. `500M`, `2G`, rounded down to 64 KiB) named `<output>.001`, `<output>.002`...
+		/// `<output>.volumes` list them and can be opened by `serve` and `extract` in place of the archive
+		#[arg(long, value_name = "SIZE", value_parser = parse_size)]
+		split: Option<u64>,
+		/// Minify html, css, js and json and losslessly recompress png and jpeg before compressing them,
+		/// files that don't get smaller are kept as they are
+		#[cfg(feature = "optimize")]
+		#[arg(long)]
+		optimize: bool,
+		/// Favor speed over size
+		#[arg(long, conflicts_with = "best")]
+		fast: bool,
+		/// Favor size over speed
+		#[arg(long)]
+		best: bool,
+	},
+	/// Periodically check archived pages for upstream changes
+	Watch {
+		/// secure upstream, `--secure=false` for plain http one
+		#[arg(short, long, default_value_t = true, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+		secure: bool,
+		/// upstream host
+		host: String,
+		/// archive dir
+		output: PathBuf,
+		/// paths (with optional query) of pages to watch
+		#[arg(required = true)]
+		paths: Vec<String>,
+		/// how often to check (eg. `30m`, `1d`)
+		#[arg(short, long, default_value = "1h", value_parser = parse_duration)]
+		interval: Duration,
+		/// compare only text content of page instead of raw bytes
+		#[arg(short, long)]
+		text: bool,
+		/// host that absolute links was rewritten into while archiving
+		#[arg(short, long, default_value = "localhost:3000")]
+		rewrite: String,
+		/// value that replaced upstream host while archiving
+		#[arg(short, long)]
+		prefix_local: Option<String>,
+		/// command to run (with `sh -c`) when page changed, diff is passed through stdin
+		#[arg(short, long)]
+		exec: Option<String>,
+		/// url to POST json summary to when page changed
+		#[arg(short, long)]
+		webhook: Option<String>,
+		/// check once and exit
+		#[arg(long)]
+		once: bool,
+	},
+	/// Fetch resources recorded in miss log of `serve` from upstream into archive
+	Fill {
+		/// secure upstream, `--secure=false` for plain http one
+		#[arg(short, long, default_value_t = true, action = ArgAction::Set, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+		secure: bool,
+		/// upstream host
+		host: String,
+		/// Archive folder or zip file
+		path: PathBuf,
+		/// Miss log written by `serve --miss-log`, urls that still can't be fetched are kept in it
+		log: PathBuf,
+		/// host that absolute links will be rewritten into
+		#[arg(short, long, default_value = "localhost:3000")]
+		rewrite: String,
+		/// provide value to replace upstream host with
+		#[arg(short, long)]
+		prefix_local: Option<String>,
+	},
+	/// Create or update full-text search index of archive folder
+	Index {
+		/// Path to archive folder
+		path: PathBuf,
+	},
+	/// Compare content of two archives, use `<archive>@<timestamp>` to compare snapshots made by watch
+	Diff {
+		/// Archive folder, zip file or snapshot to compare from
+		old: String,
+		/// Archive folder, zip file or snapshot to compare with
+		new: String,
+		/// Print result as json
+		#[arg(short, long)]
+		json: bool,
+		/// Only list changed paths without content diff
+		#[arg(short, long)]
+		quiet: bool,
+	},
+	#[cfg(any(feature = "zip", feature = "tar-archive"))]
+	/// Unpack zip or tar archive back into archive folder, links are restored
+	Extract {
+		/// Zip, tar, tar.gz or tar.zst file
+		archive: PathBuf,
+		/// Output folder (default is name of archive without extension)
+		output: Option<PathBuf>,
+		/// File whose first line is passphrase of encrypted archive (default is `ARCHIVE_IT_KEY` or prompt)
+		#[cfg(feature = "encrypt")]
+		#[arg(long)]
+		key_file: Option<PathBuf>,
+	},
+	/// Rewrite archive into another storage format without extracting it first
+	Convert {
+		/// Archive folder, zip, tar, sqlite database or warc file to read
+		input: String,
+		/// Output path
+		output: PathBuf,
+		/// Output format (default is guessed from extension of output, folder if none match)
+		#[arg(short, long)]
+		to: Option<ConvertFormat>,
+		/// Url that archive was captured from, resources become `WARC-Target-URI` under it in warc output
+		#[arg(long, default_value = "http://localhost/")]
+		base_url: String,
+	},
+	/// Re-archive sites on schedule
+	Daemon {
+		/// Path to config file (toml) that list sites to archive
+		config: PathBuf,
+		/// Archive every site once at startup instead of waiting for its schedule
+		#[arg(long)]
+		now: bool,
 	},
 }
 
-#[cfg(feature = "zip")]
-#[derive(ValueEnum, Clone, Debug)]
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+#[derive(ValueEnum, Clone, Debug, serde::Deserialize)]
 pub(crate) enum CompressFormat {
 	#[cfg(feature = "zip")]
-	zip
+	#[value(name = "zip")]
+	#[serde(rename = "zip")]
+	Zip,
+	#[cfg(feature = "tar-archive")]
+	#[value(name = "tar")]
+	#[serde(rename = "tar")]
+	Tar,
+	#[cfg(feature = "tar-archive")]
+	#[value(name = "tar.gz")]
+	#[serde(rename = "tar.gz")]
+	TarGz,
+	#[cfg(feature = "tar-archive")]
+	#[value(name = "tar.zst")]
+	#[serde(rename = "tar.zst")]
+	TarZst,
+	/// tar.zst split into independent frames, `serve` can read it without decompressing whole file
+	#[cfg(feature = "tar-archive")]
+	#[value(name = "seekable-zst")]
+	#[serde(rename = "seekable-zst")]
+	SeekableZst,
 }
-#[cfg(feature = "zip")]
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
 impl Display for CompressFormat {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-		Debug::fmt(self, f)
+		f.write_str(self.to_possible_value().unwrap().get_name())
 	}
 }
-#[cfg(feature = "zip")]
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+impl Default for CompressFormat {
+	fn default() -> Self {
+		#[cfg(feature = "zip")]
+		return CompressFormat::Zip;
+		#[cfg(not(feature = "zip"))]
+		return CompressFormat::SeekableZst;
+	}
+}
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
 impl CompressFormat {
 	pub const fn ext(&self) -> &'static str {
-		match self { CompressFormat::zip => { ".zip" } }
+		match self {
+			#[cfg(feature = "zip")]
+			CompressFormat::Zip => { ".zip" }
+			#[cfg(feature = "tar-archive")]
+			CompressFormat::Tar => { ".tar" }
+			#[cfg(feature = "tar-archive")]
+			CompressFormat::TarGz => { ".tar.gz" }
+			#[cfg(feature = "tar-archive")]
+			CompressFormat::TarZst | CompressFormat::SeekableZst => { ".tar.zst" }
+		}
+	}
+}
+
+/// Storage format that `convert` can write
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ConvertFormat {
+	Dir,
+	#[cfg(feature = "zip")]
+	Zip,
+	/// seekable zstd, so it can still be served
+	#[cfg(feature = "tar-archive")]
+	#[value(name = "tar.zst")]
+	TarZst,
+	/// single `resource` table of path, modification time and content
+	#[cfg(feature = "sqlite")]
+	Sqlite,
+	/// WARC 1.1 with a `resource` record per file
+	Warc,
+}
+
+impl ConvertFormat {
+	/// Format that extension of `path` stand for
+	pub fn of(path: &std::path::Path) -> Self {
+		let name = path.to_string_lossy();
+		match () {
+			#[cfg(feature = "zip")]
+			_ if name.ends_with(".zip") => ConvertFormat::Zip,
+			#[cfg(feature = "tar-archive")]
+			_ if name.ends_with(".tar.zst") => ConvertFormat::TarZst,
+			#[cfg(feature = "sqlite")]
+			_ if name.ends_with(".sqlite") || name.ends_with(".db") => ConvertFormat::Sqlite,
+			_ if name.ends_with(".warc") => ConvertFormat::Warc,
+			_ => ConvertFormat::Dir,
+		}
+	}
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum MatchStrategy {
+	/// same params in different order
+	Reordered,
+	/// same params after removing ones listed with `--ignore-param`
+	IgnoreParams,
+	/// variant without any query
+	NoQuery,
+	/// variant that share most params with request, at least one
+	Closest,
+}
+
+impl MatchStrategy {
+	/// Value of `X-Archive-Match` header
+	pub const fn name(&self) -> &'static str {
+		match self {
+			MatchStrategy::Reordered => "reordered",
+			MatchStrategy::IgnoreParams => "ignore-params",
+			MatchStrategy::NoQuery => "no-query",
+			MatchStrategy::Closest => "closest",
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct TtlRule {
+	/// wildcard pattern matched against request path
+	pub pattern: String,
+	/// `None` mean never refresh
+	pub ttl: Option<Duration>,
+}
+
+impl FromStr for TtlRule {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (pattern, ttl) = s.rsplit_once('=').ok_or_else(|| format!("expected <pattern>=<ttl> but got {s:?}"))?;
+		let ttl = if ttl == "never" { None } else { Some(parse_duration(ttl)?) };
+		Ok(Self { pattern: pattern.to_string(), ttl })
 	}
 }
 
+#[derive(Clone, Debug)]
+pub(crate) enum MountPoint {
+	/// match `Host` header without port
+	Host(String),
+	/// match start of path, without leading and trailing slash
+	Prefix(String),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Mount {
+	pub at: MountPoint,
+	/// archive folder or zip
+	pub path: String,
+}
+
+impl FromStr for Mount {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (at, path) = s.split_once('=').ok_or_else(|| format!("expected <host>=<path> or /<prefix>/=<path> but got {s:?}"))?;
+		let at = if at.starts_with('/') {
+			MountPoint::Prefix(at.trim_matches('/').to_string())
+		} else {
+			MountPoint::Host(at.to_ascii_lowercase())
+		};
+		if matches!(&at, MountPoint::Prefix(it) | MountPoint::Host(it) if it.is_empty()) {
+			return Err(format!("empty host or prefix in {s:?}"));
+		}
+		Ok(Self { at, path: path.to_string() })
+	}
+}
+
+/// How single zip entry is compressed
+#[cfg(feature = "zip")]
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum EntryMethod {
+	Store,
+	Deflate,
+	/// smaller and faster than deflate but not every unzip tool can read it
+	Zstd,
+}
+
+/// Compression method override of `compress`
+#[cfg(feature = "zip")]
+#[derive(Clone, Debug)]
+pub(crate) struct MethodRule {
+	pub pattern: String,
+	pub method: EntryMethod,
+}
+
+#[cfg(feature = "zip")]
+impl FromStr for MethodRule {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (pattern, method) = s.rsplit_once('=').ok_or_else(|| format!("expected <pattern>=<method> but got {s:?}"))?;
+		let method = EntryMethod::from_str(method, true)?;
+		Ok(Self { pattern: pattern.to_string(), method })
+	}
+}
+
+/// Timestamp stored for entries of reproducible archive
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum EntryTime {
+	Fixed(u64),
+	/// modification time of archived file, which is time it was captured
+	Capture,
+}
+
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+impl FromStr for EntryTime {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"capture" => Ok(Self::Capture),
+			s => s.parse().map(Self::Fixed).map_err(|_| format!("expected unix seconds or `capture` but got {s:?}")),
+		}
+	}
+}
+
+/// Parse duration like `30s`, `15m`, `12h` or `7d`
+pub(crate) fn parse_duration(s: &str) -> Result<Duration, String> {
+	let split = s.find(|it: char| !it.is_ascii_digit()).unwrap_or(s.len());
+	let (n, unit) = s.split_at(split);
+	let n: u64 = n.parse().map_err(|_| format!("invalid duration {s:?}"))?;
+	let unit_secs = match unit {
+		"" | "s" => 1,
+		"m" => 60,
+		"h" => 60 * 60,
+		"d" => 60 * 60 * 24,
+		_ => return Err(format!("invalid duration unit {unit:?}")),
+	};
+	let secs = n.checked_mul(unit_secs).ok_or_else(|| format!("duration {s:?} is too long"))?;
+	Ok(Duration::from_secs(secs))
+}
+
+/// Size of split volumes in bytes with optional binary unit, rounded down to multiple of 64 KiB
+/// so that volumes can be mapped next to each other whatever page size is
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+pub(crate) fn parse_size(s: &str) -> Result<u64, String> {
+	let split = s.find(|it: char| !it.is_ascii_digit()).unwrap_or(s.len());
+	let (n, unit) = s.split_at(split);
+	let n: u64 = n.parse().map_err(|_| format!("invalid size {s:?}"))?;
+	let shift = match unit.trim_end_matches("iB").trim_end_matches('B') {
+		"" => 0,
+		"K" | "k" => 10,
+		"M" | "m" => 20,
+		"G" | "g" => 30,
+		"T" | "t" => 40,
+		_ => return Err(format!("invalid size unit {unit:?}")),
+	};
+	let size = n.checked_mul(1 << shift).ok_or_else(|| format!("size {s:?} is too large"))? & !0xffff;
+	if size == 0 {
+		return Err("size must be at least 64K".to_string());
+	}
+	Ok(size)
+}
+
 #[derive(Args)]
 pub(crate) struct HttpConfig {
 	#[arg(short, long, default_value_t = 3000)]
 	/// Port to listen
 	pub listen: u16,
 
-	/// Url to rewrite into (default is localhost:port, currently unsupported)
+	/// Host that absolute links are rewritten into (default is localhost:port),
+	/// `serve` rewrite links to this host when archive is mounted under another url
 	#[arg(short, long)]
 	pub rewrite: Option<String>,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn duration() {
+		assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+		assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+		assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(24 * 60 * 60)));
+		assert!(parse_duration("1w").is_err());
+		assert!(parse_duration("d").is_err());
+		assert!(parse_duration(&format!("{}d", u64::MAX / 60)).is_err());
+	}
+}
@@ -1,17 +1,236 @@
-use std::fs::File;
+#[cfg(feature = "zip")]
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{File, Metadata};
+#[cfg(feature = "zip")]
+use std::fs::OpenOptions;
+use std::io;
+#[cfg(feature = "zip")]
+use std::io::{Read, Seek};
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 
-use tracing::info;
+use tracing::{error, info};
+#[cfg(feature = "zip")]
 use zip::write::FileOptions;
-use zip::ZipWriter;
+#[cfg(feature = "zip")]
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
 
-use crate::cli::{CompressFormat, Config};
+use crate::cli::{CompressFormat, Config, EntryTime};
+#[cfg(feature = "zip")]
+use crate::cli::{EntryMethod, MethodRule};
 use crate::utils::read_dir_recursive;
+#[cfg(feature = "zip")]
+use crate::utils::wildcard_match;
+
+/// Interval between progress reports
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+/// 1980-01-01, earliest time zip can store
+const ZIP_EPOCH: u64 = 315532800;
+/// Extensions of files that are compressed already
+#[cfg(feature = "zip")]
+static COMPRESSED_EXT: &[&str] = &[".gz", ".tgz", ".br", ".zst", ".xz", ".bz2", ".7z", ".zip", ".jar", ".woff", ".woff2", ".webp", ".avif", ".heic"];
 
 pub(crate) async fn dir(cfg: Config) {
-	let Config::Compress { path, format, output } = cfg else { unreachable!() };
-	let path: &Path = path.as_ref();
-	let output: PathBuf = if let Some(mut output) = output {
+	#[cfg(feature = "zip")]
+	let Config::Compress { method: methods, update, .. } = &cfg else { unreachable!() };
+	#[cfg(feature = "zip")]
+	let (methods, update) = (methods.clone(), *update);
+	#[cfg(feature = "encrypt")]
+	let Config::Compress { encrypt, key_file, .. } = &cfg else { unreachable!() };
+	#[cfg(feature = "encrypt")]
+	let encrypt = *encrypt;
+	#[cfg(feature = "encrypt")]
+	if let Some(key_file) = key_file {
+		crate::crypto::use_key_file(key_file.clone());
+	}
+	#[cfg(feature = "optimize")]
+	let Config::Compress { optimize, .. } = cfg else { unreachable!() };
+	let Config::Compress { path, format, output, jobs, reproducible, mtime, fast, best, split, .. } = cfg else { unreachable!() };
+	let path = PathBuf::from(path);
+	let output = output_path(&path, &format, output);
+	// tar is encrypted as a whole
+	#[cfg(feature = "encrypt")]
+	let output = if encrypt && format.ext().starts_with(".tar") {
+		let mut name = output.into_os_string();
+		name.push(".age");
+		PathBuf::from(name)
+	} else {
+		output
+	};
+	let reproducible = reproducible.then(|| mtime.unwrap_or_else(|| {
+		let epoch = std::env::var("SOURCE_DATE_EPOCH").ok().and_then(|it| it.parse().ok());
+		EntryTime::Fixed(epoch.unwrap_or(ZIP_EPOCH))
+	}));
+	let preset = if fast { Preset::Fast } else if best { Preset::Best } else { Preset::Default };
+	let config = CompressConfig {
+		jobs: jobs.unwrap_or_else(default_jobs),
+		reproducible,
+		preset,
+		split,
+		#[cfg(feature = "zip")]
+		methods,
+		#[cfg(feature = "zip")]
+		update,
+		#[cfg(feature = "encrypt")]
+		encrypt,
+		#[cfg(feature = "optimize")]
+		optimize: optimize.then(|| crate::optimize::Optimizer::new(preset)),
+	};
+	let res = {
+		let output = output.clone();
+		tokio::task::spawn_blocking(move || compress(&path, &format, &output, &config)).await.unwrap()
+	};
+	match res {
+		Ok(()) => info!("finished compress folder {output:?}"),
+		Err(e) => {
+			error!("failed to compress into {output:?}: {e}");
+			std::process::exit(1);
+		}
+	}
+}
+
+/// Options shared by every compress format
+pub struct CompressConfig {
+	/// number of threads that compress in parallel
+	pub jobs: usize,
+	/// timestamp of entries when output must not depend on when and where it's made
+	pub reproducible: Option<EntryTime>,
+	pub preset: Preset,
+	/// size of volumes that output is split into
+	pub split: Option<u64>,
+	/// overrides of compression method of zip entries
+	#[cfg(feature = "zip")]
+	pub methods: Vec<MethodRule>,
+	/// copy unchanged entries from existing output instead of compressing everything again
+	#[cfg(feature = "zip")]
+	pub update: bool,
+	/// encrypt zip entries with AES-256 or tar with age, using passphrase from [`crate::crypto::passphrase`]
+	#[cfg(feature = "encrypt")]
+	pub encrypt: bool,
+	/// minify text and recompress images before they're compressed
+	#[cfg(feature = "optimize")]
+	pub optimize: Option<crate::optimize::Optimizer>,
+}
+
+impl Default for CompressConfig {
+	fn default() -> Self {
+		Self {
+			jobs: default_jobs(),
+			reproducible: None,
+			preset: Preset::Default,
+			split: None,
+			#[cfg(feature = "zip")]
+			methods: vec![],
+			#[cfg(feature = "zip")]
+			update: false,
+			#[cfg(feature = "encrypt")]
+			encrypt: false,
+			#[cfg(feature = "optimize")]
+			optimize: None,
+		}
+	}
+}
+
+/// Trade-off between speed and size, each preset map to fixed level of every codec
+#[derive(Clone, Copy, Debug)]
+pub enum Preset {
+	Fast,
+	Default,
+	Best,
+}
+
+impl Preset {
+	fn deflate(self) -> i32 {
+		match self { Preset::Fast => 1, Preset::Default => 6, Preset::Best => 9 }
+	}
+
+	pub(crate) fn zstd(self) -> i32 {
+		match self { Preset::Fast => 3, Preset::Default => 9, Preset::Best => 19 }
+	}
+}
+
+impl CompressConfig {
+	/// Unix timestamp of entry in reproducible mode
+	fn mtime(&self, meta: &Metadata) -> Option<u64> {
+		match self.reproducible? {
+			EntryTime::Fixed(it) => Some(it),
+			EntryTime::Capture => unix_time(meta),
+		}
+	}
+
+	/// Fixed permission of entry in reproducible mode
+	fn permissions(&self, is_dir: bool) -> Option<u32> {
+		self.reproducible.map(|_| if is_dir { 0o755 } else { 0o644 })
+	}
+
+	/// Log what `--optimize` saved
+	fn report(&self) {
+		#[cfg(feature = "optimize")]
+		if let Some(optimizer) = &self.optimize {
+			optimizer.report();
+		}
+	}
+
+	#[cfg(feature = "zip")]
+	/// Compression method of zip entry `name`, `head` is beginning of its content
+	/// that tell type of files that forward stored without extension
+	fn method(&self, name: &str, head: &[u8]) -> EntryMethod {
+		let guess = mime_guess::from_path(name).first();
+		let mime = guess.as_ref().map(|it| it.essence_str()).or_else(|| sniff(head)).unwrap_or_default();
+		if let Some(rule) = self.methods.iter().find(|it| wildcard_match(&it.pattern, name) || wildcard_match(&it.pattern, mime)) {
+			return rule.method;
+		}
+		let media = mime.starts_with("image/") && !matches!(mime, "image/svg+xml" | "image/bmp" | "image/x-icon" | "image/vnd.microsoft.icon")
+			|| mime.starts_with("video/")
+			|| mime.starts_with("audio/") && !mime.ends_with("wav");
+		let packed = matches!(mime, "font/woff" | "font/woff2" | "application/font-woff" | "application/zip" | "application/gzip"
+			| "application/x-gzip" | "application/zstd" | "application/x-7z-compressed" | "application/x-xz" | "application/x-bzip2");
+		if media || packed || COMPRESSED_EXT.iter().any(|it| name.ends_with(it)) {
+			EntryMethod::Store
+		} else {
+			EntryMethod::Deflate
+		}
+	}
+}
+
+/// Mime type of already compressed content by its magic number
+#[cfg(feature = "zip")]
+fn sniff(head: &[u8]) -> Option<&'static str> {
+	let at = |pos: usize, magic: &[u8]| head.get(pos..pos + magic.len()) == Some(magic);
+	Some(match () {
+		_ if at(0, b"\xFF\xD8\xFF") => "image/jpeg",
+		_ if at(0, b"\x89PNG") => "image/png",
+		_ if at(0, b"GIF8") => "image/gif",
+		_ if at(0, b"RIFF") && at(8, b"WEBP") => "image/webp",
+		_ if at(4, b"ftypavif") => "image/avif",
+		_ if at(4, b"ftyp") => "video/mp4",
+		_ if at(0, b"\x1A\x45\xDF\xA3") => "video/webm",
+		_ if at(0, b"OggS") => "audio/ogg",
+		_ if at(0, b"ID3") => "audio/mpeg",
+		_ if at(0, b"wOFF") => "font/woff",
+		_ if at(0, b"wOF2") => "font/woff2",
+		_ if at(0, b"\x1F\x8B") => "application/gzip",
+		_ if at(0, b"\x28\xB5\x2F\xFD") => "application/zstd",
+		_ if at(0, b"PK\x03\x04") => "application/zip",
+		_ => return None,
+	})
+}
+
+/// Modification time of file in unix seconds
+fn unix_time(meta: &Metadata) -> Option<u64> {
+	Some(meta.modified().ok()?.duration_since(SystemTime::UNIX_EPOCH).ok()?.as_secs())
+}
+
+fn default_jobs() -> usize {
+	std::thread::available_parallelism().map_or(1, |it| it.get())
+}
+
+/// Resolve output file name, default to name of archive folder with extension of `format`
+pub fn output_path(path: &Path, format: &CompressFormat, output: Option<String>) -> PathBuf {
+	if let Some(mut output) = output {
 		if !output.ends_with(format.ext()) {
 			output.push_str(format.ext());
 		}
@@ -20,31 +239,784 @@ pub(crate) async fn dir(cfg: Config) {
 		let mut name = path.canonicalize().expect("Output dir").file_name().unwrap().to_os_string();
 		name.push(format.ext());
 		PathBuf::from(name)
-	};
+	}
+}
+
+/// Compress folder `path` into `output`, partially written output is removed on error
+pub fn compress(path: &Path, format: &CompressFormat, output: &Path, cfg: &CompressConfig) -> io::Result<()> {
+	if !path.is_dir() {
+		return Err(io::Error::new(io::ErrorKind::NotFound, format!("{path:?} is not a folder")));
+	}
+	#[cfg(feature = "encrypt")]
+	if cfg.encrypt {
+		#[cfg(feature = "zip")]
+		if cfg.update {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "encrypted zip can't be updated"));
+		}
+		// asked before any work, workers only read it
+		crate::crypto::passphrase(true)?;
+	}
+	#[cfg(feature = "zip")]
+	if cfg.update {
+		if !matches!(format, CompressFormat::Zip) {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("only zip can be updated, not {format}")));
+		}
+		if cfg.split.is_some() {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "split zip can't be updated"));
+		}
+		if output.exists() {
+			// existing archive is kept as it is on error
+			return update_zip(path, output, cfg).inspect(|()| cfg.report());
+		}
+	}
+	let res = write_format(path, format, output, cfg).and_then(|()| match cfg.split {
+		Some(size) => crate::volume::split(output, size).map(|_| ()),
+		None => Ok(()),
+	});
+	if res.is_err() {
+		let _ = std::fs::remove_file(output);
+	}
+	res.inspect(|()| cfg.report())
+}
+
+fn write_format(path: &Path, format: &CompressFormat, output: &Path, cfg: &CompressConfig) -> io::Result<()> {
 	match format {
-		CompressFormat::zip => {
-			compress_zip(path, &output)
+		#[cfg(feature = "zip")]
+		CompressFormat::Zip => {
+			compress_zip(path, output, cfg, None)
+		}
+		#[cfg(feature = "tar-archive")]
+		CompressFormat::Tar => {
+			compress_tar(path, TarOutput::create(output, cfg)?, cfg)?.sync_all()
+		}
+		#[cfg(feature = "tar-archive")]
+		CompressFormat::TarGz => {
+			let writer = flate2::write::GzEncoder::new(TarOutput::create(output, cfg)?, flate2::Compression::new(cfg.preset.deflate() as u32));
+			compress_tar(path, writer, cfg)?.finish()?.sync_all()
+		}
+		#[cfg(feature = "tar-archive")]
+		CompressFormat::TarZst => {
+			let mut writer = zstd::Encoder::new(TarOutput::create(output, cfg)?, cfg.preset.zstd())?;
+			writer.multithread(cfg.jobs as u32)?;
+			compress_tar(path, writer, cfg)?.finish()?.sync_all()
+		}
+		#[cfg(feature = "tar-archive")]
+		CompressFormat::SeekableZst => {
+			let writer = crate::seekable::SeekableWriter::new(TarOutput::create(output, cfg)?, cfg.preset.zstd(), cfg.jobs);
+			compress_tar(path, writer, cfg)?.finish()?.sync_all()
 		}
 	}
-	info!("finished compress folder {output:?}");
 }
 
-pub fn compress_zip(dir: &Path, output: &Path) {
-	let out_file = File::create(output).unwrap();
-	let mut writer = ZipWriter::new(out_file);
-	let root = dir;
-	let option = FileOptions::default()
-		.compression_level(Some(9));
-	for (x, is_dir) in read_dir_recursive(dir) {
-		let path = x.strip_prefix(root).unwrap();
-		if is_dir {
-			writer.add_directory(path.to_string_lossy(), option).unwrap();
+/// File that tar formats are written into, wrapped in age when encrypted
+#[cfg(feature = "tar-archive")]
+enum TarOutput {
+	Plain(File),
+	#[cfg(feature = "encrypt")]
+	Age(age::stream::StreamWriter<File>),
+}
+
+#[cfg(feature = "tar-archive")]
+impl TarOutput {
+	fn create(output: &Path, cfg: &CompressConfig) -> io::Result<Self> {
+		let file = File::create(output)?;
+		#[cfg(feature = "encrypt")]
+		if cfg.encrypt {
+			return crate::crypto::age_writer(file).map(Self::Age);
+		}
+		let _ = cfg;
+		Ok(Self::Plain(file))
+	}
+
+	/// Write last encrypted chunk and flush file to disk
+	fn sync_all(self) -> io::Result<()> {
+		match self {
+			Self::Plain(file) => file.sync_all(),
+			#[cfg(feature = "encrypt")]
+			Self::Age(writer) => writer.finish()?.sync_all(),
+		}
+	}
+}
+
+#[cfg(feature = "tar-archive")]
+impl Write for TarOutput {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match self {
+			Self::Plain(file) => file.write(buf),
+			#[cfg(feature = "encrypt")]
+			Self::Age(writer) => writer.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		match self {
+			Self::Plain(file) => file.flush(),
+			#[cfg(feature = "encrypt")]
+			Self::Age(writer) => writer.flush(),
+		}
+	}
+}
+
+/// File or folder of archive
+struct Item {
+	path: PathBuf,
+	is_dir: bool,
+	/// alias of content stored under another entry
+	link: Option<Link>,
+}
+
+enum Link {
+	/// another name of file stored earlier, relative to archive root
+	Hard(PathBuf),
+	/// symbolic link with target as it is on disk
+	Symbolic(PathBuf),
+}
+
+/// Files and folders under `dir`, sorted so output doesn't depend on order of `read_dir`.
+/// Content that forward linked under several names is kept once and other names become links
+fn sorted_entries(dir: &Path) -> io::Result<Vec<Item>> {
+	let mut entries = read_dir_recursive(dir);
+	entries.sort_unstable();
+	#[cfg(unix)]
+	let mut linked = std::collections::HashMap::new();
+	let mut items: Vec<Item> = Vec::with_capacity(entries.len());
+	for (path, is_dir) in entries {
+		// content of linked folder is already covered by the link
+		if items.last().is_some_and(|it| matches!(it.link, Some(Link::Symbolic(_))) && it.is_dir && path.starts_with(&it.path)) {
 			continue;
 		}
-		info!("compressing {path:?}");
-		writer.start_file(path.to_string_lossy(), option).unwrap();
-		let mut f = File::open(x).unwrap();
-		std::io::copy(&mut f, &mut writer).unwrap();
+		let meta = path.symlink_metadata().map_err(at(&path))?;
+		let mut link = None;
+		if meta.file_type().is_symlink() {
+			link = Some(Link::Symbolic(std::fs::read_link(&path).map_err(at(&path))?));
+		}
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::MetadataExt;
+			if link.is_none() && !is_dir && meta.nlink() > 1 {
+				let name = path.strip_prefix(dir).unwrap();
+				let target = linked.entry((meta.dev(), meta.ino())).or_insert_with(|| name.to_path_buf());
+				if target != name {
+					link = Some(Link::Hard(target.clone()));
+				}
+			}
+		}
+		items.push(Item { path, is_dir, link });
+	}
+	Ok(items)
+}
+
+/// Attach path of file that failed to error
+pub(crate) fn at(path: &Path) -> impl FnOnce(io::Error) -> io::Error + '_ {
+	move |e| io::Error::new(e.kind(), format!("{path:?}: {e}"))
+}
+
+/// Count compressed files and bytes, report them periodically
+struct Progress {
+	files: AtomicUsize,
+	bytes: AtomicU64,
+	total_files: usize,
+	total_bytes: u64,
+	last: Mutex<Instant>,
+}
+
+impl Progress {
+	fn new(entries: &[Item]) -> Self {
+		let files: Vec<_> = entries.iter().filter(|it| !it.is_dir).collect();
+		Self {
+			files: AtomicUsize::new(0),
+			bytes: AtomicU64::new(0),
+			total_files: files.len(),
+			total_bytes: files.iter().filter(|it| it.link.is_none()).filter_map(|it| it.path.metadata().ok()).map(|it| it.len()).sum(),
+			last: Mutex::new(Instant::now()),
+		}
+	}
+
+	fn add(&self, bytes: u64) {
+		let files = self.files.fetch_add(1, Ordering::Relaxed) + 1;
+		let bytes = self.bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+		let mut last = self.last.lock().unwrap();
+		if last.elapsed() >= PROGRESS_INTERVAL || files == self.total_files {
+			*last = Instant::now();
+			let percent = (bytes * 100).checked_div(self.total_bytes).unwrap_or(100);
+			info!("compressed {files}/{} files, {}/{} MiB ({percent}%)", self.total_files, bytes >> 20, self.total_bytes >> 20);
+		}
+	}
+}
+
+/// Write content of `dir` as tar into `writer`, aliases are stored as hard links or symbolic links
+#[cfg(feature = "tar-archive")]
+pub fn compress_tar<W: Write>(dir: &Path, writer: W, cfg: &CompressConfig) -> io::Result<W> {
+	let mut builder = tar::Builder::new(writer);
+	// owner and device are left out of reproducible archive
+	let mode = if cfg.reproducible.is_some() { tar::HeaderMode::Deterministic } else { tar::HeaderMode::Complete };
+	let entries = sorted_entries(dir)?;
+	let progress = Progress::new(&entries);
+	for Item { path: x, is_dir, link } in entries {
+		let path = x.strip_prefix(dir).unwrap();
+		let meta = x.symlink_metadata().map_err(at(&x))?;
+		let mut header = tar::Header::new_gnu();
+		header.set_metadata_in_mode(&meta, mode);
+		if let Some(mtime) = cfg.mtime(&meta) {
+			header.set_mtime(mtime);
+		}
+		if let Some(permissions) = cfg.permissions(is_dir) {
+			header.set_mode(permissions);
+		}
+		match link {
+			Some(Link::Symbolic(target)) => {
+				builder.append_link(&mut header, path, target).map_err(at(&x))?;
+			}
+			Some(Link::Hard(target)) => {
+				header.set_entry_type(tar::EntryType::Link);
+				header.set_size(0);
+				builder.append_link(&mut header, path, target).map_err(at(&x))?;
+			}
+			None if is_dir => {
+				builder.append_data(&mut header, path, io::empty()).map_err(at(&x))?;
+				continue;
+			}
+			None => {
+				#[cfg(feature = "optimize")]
+				if let Some(optimizer) = &cfg.optimize {
+					if let Some(data) = optimizer.optimized(&x, &path.to_string_lossy()).map_err(at(&x))? {
+						header.set_size(data.len() as u64);
+						builder.append_data(&mut header, path, data.as_slice()).map_err(at(&x))?;
+						progress.add(meta.len());
+						continue;
+					}
+				}
+				let file = File::open(&x).map_err(at(&x))?;
+				builder.append_data(&mut header, path, file).map_err(at(&x))?;
+				progress.add(meta.len());
+				continue;
+			}
+		}
+		if !is_dir {
+			progress.add(0);
+		}
+	}
+	builder.into_inner()
+}
+
+/// Compress `dir` into new zip next to `output` reusing unchanged entries of it, then replace `output` with it
+#[cfg(feature = "zip")]
+fn update_zip(dir: &Path, output: &Path, cfg: &CompressConfig) -> io::Result<()> {
+	let previous = ZipArchive::new(File::open(output)?)?;
+	let mut part = output.as_os_str().to_os_string();
+	part.push(".part");
+	let part = PathBuf::from(part);
+	let res = compress_zip(dir, &part, cfg, Some(previous)).and_then(|()| std::fs::rename(&part, output));
+	if res.is_err() {
+		let _ = std::fs::remove_file(&part);
+	}
+	res
+}
+
+/// Entry of zip that output is updated from
+#[cfg(feature = "zip")]
+struct Previous {
+	index: usize,
+	size: u64,
+	/// msdos date and time
+	time: (u16, u16),
+	crc32: u32,
+}
+
+/// Entries larger than this are built in a file next to output, so ones waiting for earlier entries don't fill memory
+#[cfg(feature = "zip")]
+const SPOOL_SIZE: u64 = 4 << 20;
+
+/// Buffer of entry that's written once and read back, in memory or in a file removed once it's dropped
+#[cfg(feature = "zip")]
+enum Spool {
+	Memory(io::Cursor<Vec<u8>>),
+	File(File, PathBuf),
+}
+
+#[cfg(feature = "zip")]
+impl Spool {
+	/// Buffer for about `size` bytes, file at `path` is used when it's larger than `SPOOL_SIZE`
+	fn new(path: PathBuf, size: u64) -> io::Result<Self> {
+		if size <= SPOOL_SIZE {
+			return Ok(Self::Memory(io::Cursor::default()));
+		}
+		let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)?;
+		Ok(Self::File(file, path))
+	}
+
+	/// Empty buffer to write it again
+	fn clear(&mut self) -> io::Result<()> {
+		match self {
+			Self::Memory(it) => it.get_mut().clear(),
+			Self::File(it, _) => it.set_len(0)?,
+		}
+		self.rewind()
+	}
+}
+
+#[cfg(feature = "zip")]
+impl Read for Spool {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		match self {
+			Self::Memory(it) => it.read(buf),
+			Self::File(it, _) => it.read(buf),
+		}
+	}
+}
+
+#[cfg(feature = "zip")]
+impl Write for Spool {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match self {
+			Self::Memory(it) => it.write(buf),
+			Self::File(it, _) => it.write(buf),
+		}
 	}
-	writer.finish().expect("Finish writing zip").sync_all().unwrap();
-}
\ No newline at end of file
+
+	fn flush(&mut self) -> io::Result<()> {
+		match self {
+			Self::Memory(it) => it.flush(),
+			Self::File(it, _) => it.flush(),
+		}
+	}
+}
+
+#[cfg(feature = "zip")]
+impl Seek for Spool {
+	fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+		match self {
+			Self::Memory(it) => it.seek(pos),
+			Self::File(it, _) => it.seek(pos),
+		}
+	}
+}
+
+#[cfg(feature = "zip")]
+impl Drop for Spool {
+	fn drop(&mut self) {
+		if let Self::File(_, path) = self {
+			let _ = std::fs::remove_file(path);
+		}
+	}
+}
+
+/// Path of `kind` spool file of entry `index` next to `output`
+#[cfg(feature = "zip")]
+fn spool_path(output: &Path, index: usize, kind: &str) -> PathBuf {
+	let mut path = output.as_os_str().to_os_string();
+	path.push(format!(".{index}.{kind}"));
+	PathBuf::from(path)
+}
+
+/// Entry ready to be copied into zip
+#[cfg(feature = "zip")]
+enum Packed {
+	/// single entry zip made by `deflate_entry`
+	New(Spool),
+	/// index of unchanged entry in previous zip
+	Kept(usize),
+	/// AES encrypted entry, written by hand since zip crate can't encrypt
+	#[cfg(feature = "encrypt")]
+	Sealed(Sealed),
+}
+
+/// File entry whose compressed data is encrypted
+#[cfg(all(feature = "zip", feature = "encrypt"))]
+struct Sealed {
+	name: String,
+	modified: zip::DateTime,
+	mode: u32,
+	/// uncompressed size
+	size: u64,
+	/// how data was compressed before encryption
+	method: u16,
+	/// salt, password verifier, encrypted data and authentication code
+	payload: Spool,
+}
+
+/// What zip crate doesn't write, patched into headers once zip is finished
+#[cfg(feature = "zip")]
+struct Header {
+	mode: u32,
+	/// uncompressed size of AES encrypted entry
+	sealed: Option<u64>,
+}
+
+/// Deflate entries on `cfg.jobs` threads into single entry zips, in memory or spooled next to `output` when they're large,
+/// then copy them into `output` in order.
+/// Files that didn't change since `previous` was made are copied from it instead
+#[cfg(feature = "zip")]
+pub fn compress_zip(dir: &Path, output: &Path, cfg: &CompressConfig, mut previous: Option<ZipArchive<File>>) -> io::Result<()> {
+	let entries = sorted_entries(dir)?;
+	let progress = Progress::new(&entries);
+	let mut known = HashMap::new();
+	if let Some(zip) = &mut previous {
+		for index in 0..zip.len() {
+			let entry = zip.by_index_raw(index)?;
+			// links and folders are cheap to make again
+			if entry.is_file() && entry.unix_mode().is_none_or(|it| it & 0o170000 == 0o100000) {
+				let time = entry.last_modified();
+				let previous = Previous { index, size: entry.size(), time: (time.datepart(), time.timepart()), crc32: entry.crc32() };
+				known.insert(entry.name().to_string(), previous);
+			}
+		}
+	}
+	let kept = AtomicUsize::new(0);
+	// central directory is read back to restore modes of entries
+	let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(output)?;
+	let mut writer = ZipWriter::new(file);
+	let option = FileOptions::default();
+	// finished entries waiting for earlier ones are bounded to keep memory in check
+	let jobs = cfg.jobs.max(1);
+	let window = jobs * 4;
+	let next = AtomicUsize::new(0);
+	let written = (Mutex::new(0), std::sync::Condvar::new());
+	let (tx, rx) = std::sync::mpsc::channel();
+	let mut headers = Vec::with_capacity(entries.len());
+	std::thread::scope(|s| {
+		for _ in 0..jobs {
+			let (tx, next, written, entries, progress, known, kept) = (tx.clone(), &next, &written, &entries, &progress, &known, &kept);
+			s.spawn(move || loop {
+				let i = next.fetch_add(1, Ordering::Relaxed);
+				let Some(item) = entries.get(i) else { break };
+				drop(written.1.wait_while(written.0.lock().unwrap(), |it| i >= *it + window).unwrap());
+				let res = match unchanged(dir, item, known, cfg) {
+					Ok(Some(index)) => {
+						kept.fetch_add(1, Ordering::Relaxed);
+						Ok(Packed::Kept(index))
+					}
+					Ok(None) => match deflate_entry(dir, item, option, cfg, &spool_path(output, i, "spool")) {
+						#[cfg(feature = "encrypt")]
+						Ok(buf) if cfg.encrypt && !item.is_dir && item.link.is_none() => seal(buf, spool_path(output, i, "aes")).map(Packed::Sealed),
+						res => res.map(Packed::New),
+					},
+					Err(e) => Err(e),
+				}.map_err(at(&item.path));
+				if !item.is_dir {
+					progress.add(if item.link.is_some() { 0 } else { item.path.metadata().map_or(0, |it| it.len()) });
+				}
+				if tx.send((i, res)).is_err() { break; }
+			});
+		}
+		drop(tx);
+		let mut pending = BTreeMap::new();
+		let mut index = 0;
+		for (i, res) in rx {
+			pending.insert(i, res);
+			while let Some(res) = pending.remove(&index) {
+				let res = res.and_then(|packed| {
+					let mut zip;
+					let entry = match packed {
+						Packed::New(buf) => {
+							zip = ZipArchive::new(buf)?;
+							zip.by_index_raw(0)?
+						}
+						Packed::Kept(index) => previous.as_mut().unwrap().by_index_raw(index)?,
+						#[cfg(feature = "encrypt")]
+						Packed::Sealed(entry) => {
+							let option = FileOptions::default().compression_method(CompressionMethod::Stored).last_modified_time(entry.modified);
+							writer.start_file_with_extra_data(&entry.name, option)?;
+							writer.write_all(&crate::crypto::aes_extra(entry.method))?;
+							writer.end_extra_data()?;
+							let mut payload = entry.payload;
+							payload.rewind()?;
+							io::copy(&mut payload, &mut writer)?;
+							headers.push(Header { mode: entry.mode, sealed: Some(entry.size) });
+							return Ok(());
+						}
+					};
+					headers.push(Header { mode: entry.unix_mode().unwrap_or(0o100644), sealed: None });
+					writer.raw_copy_file(entry)?;
+					Ok(())
+				});
+				index += 1;
+				let mut done = written.0.lock().unwrap();
+				// on error unblock waiting threads, they stop once channel is dropped
+				*done = if res.is_err() { usize::MAX / 2 } else { index };
+				written.1.notify_all();
+				drop(done);
+				if res.is_err() {
+					next.store(entries.len(), Ordering::Relaxed);
+					return res;
+				}
+			}
+		}
+		Ok(())
+	})?;
+	let mut file = writer.finish()?;
+	patch_headers(&mut file, &headers)?;
+	if previous.is_some() {
+		let names: HashSet<_> = entries.iter().map(|it| entry_name(dir, &it.path)).collect();
+		let removed = known.keys().filter(|it| !names.contains(*it)).count();
+		let files = entries.iter().filter(|it| !it.is_dir && it.link.is_none()).count();
+		let kept = kept.into_inner();
+		info!("kept {kept} unchanged files, compressed {} changed or new files, removed {removed} files", files - kept);
+	}
+	file.sync_all()
+}
+
+/// Index of `item` in previous zip when its size and modification time or checksum are still the same
+#[cfg(feature = "zip")]
+fn unchanged(dir: &Path, item: &Item, known: &HashMap<String, Previous>, cfg: &CompressConfig) -> io::Result<Option<usize>> {
+	if item.is_dir || item.link.is_some() {
+		return Ok(None);
+	}
+	let Some(previous) = known.get(&entry_name(dir, &item.path)) else { return Ok(None) };
+	let meta = item.path.metadata()?;
+	// optimized entries are smaller than their file, only time or checksum can tell
+	#[cfg(feature = "optimize")]
+	let optimized = cfg.optimize.is_some();
+	#[cfg(not(feature = "optimize"))]
+	let optimized = false;
+	if meta.len() != previous.size && !optimized {
+		return Ok(None);
+	}
+	// fixed timestamp of reproducible mode doesn't tell anything about content
+	if !matches!(cfg.reproducible, Some(EntryTime::Fixed(_))) {
+		if let Some(time) = unix_time(&meta).map(zip_time) {
+			if (time.datepart(), time.timepart()) == previous.time {
+				return Ok(Some(previous.index));
+			}
+		}
+	}
+	// touched but maybe not modified, compare content
+	let mut f = File::open(&item.path)?;
+	let mut hasher = crc32fast::Hasher::new();
+	let mut buf = vec![0; 64 << 10];
+	loop {
+		match f.read(&mut buf)? {
+			0 => break,
+			n => hasher.update(&buf[..n]),
+		}
+	}
+	Ok((hasher.finalize() == previous.crc32).then_some(previous.index))
+}
+
+/// Name of zip entry of `path` under `dir`
+#[cfg(feature = "zip")]
+fn entry_name(dir: &Path, path: &Path) -> String {
+	path.strip_prefix(dir).unwrap().to_string_lossy().replace('\\', "/")
+}
+
+/// `raw_copy_file` keep only permission bits of entries, put file type (folder, symbolic link) back into central directory.
+/// Encrypted entries are written as stored, they're marked as AES with their real size in both of their headers
+#[cfg(feature = "zip")]
+fn patch_headers(file: &mut File, headers: &[Header]) -> io::Result<()> {
+	let invalid = || io::Error::new(io::ErrorKind::InvalidData, "central directory not found");
+	let read_at = |file: &mut File, pos: u64, len: usize| -> io::Result<Vec<u8>> {
+		let mut buf = vec![0; len];
+		file.seek(io::SeekFrom::Start(pos))?;
+		file.read_exact(&mut buf)?;
+		Ok(buf)
+	};
+	let u16_at = |buf: &[u8], pos: usize| u16::from_le_bytes([buf[pos], buf[pos + 1]]) as usize;
+	let u32_at = |buf: &[u8], pos: usize| u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap()) as u64;
+	let u64_at = |buf: &[u8], pos: usize| u64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap());
+
+	let len = file.seek(io::SeekFrom::End(0))?;
+	let tail_start = len.saturating_sub(22 + u16::MAX as u64);
+	let tail = read_at(file, tail_start, (len - tail_start) as usize)?;
+	let eocd = (0..tail.len().saturating_sub(21)).rev().find(|&it| u32_at(&tail, it) == 0x06054b50).ok_or_else(invalid)?;
+	let (mut cd, mut cd_len) = (u32_at(&tail, eocd + 16), u32_at(&tail, eocd + 12));
+	if cd == u32::MAX as u64 || cd_len == u32::MAX as u64 {
+		// zip64 end of central directory locator is right before end of central directory
+		let locator = eocd.checked_sub(20).ok_or_else(invalid)?;
+		let record = read_at(file, u64_at(&tail, locator + 8), 56)?;
+		(cd_len, cd) = (u64_at(&record, 40), u64_at(&record, 48));
+	}
+	// version needed, flags, method, crc and uncompressed size are at same distance in local and central header
+	let seal = |buf: &mut [u8], version: usize, size: u64| {
+		buf[version..version + 2].copy_from_slice(&51u16.to_le_bytes());
+		buf[version + 2] |= 1;
+		buf[version + 4..version + 6].copy_from_slice(&99u16.to_le_bytes());
+		buf[version + 10..version + 14].fill(0);
+		buf[version + 18..version + 22].copy_from_slice(&(size as u32).to_le_bytes());
+	};
+	let mut buf = read_at(file, cd, cd_len as usize)?;
+	let mut pos = 0;
+	for header in headers {
+		if buf.len() < pos + 46 || u32_at(&buf, pos) != 0x02014b50 { return Err(invalid()); }
+		let (name_len, extra_len) = (u16_at(&buf, pos + 28), u16_at(&buf, pos + 30));
+		buf[pos + 38..pos + 42].copy_from_slice(&(header.mode << 16).to_le_bytes());
+		if let Some(size) = header.sealed {
+			seal(&mut buf[pos..], 6, size);
+			let mut local = u32_at(&buf, pos + 42);
+			if local == u32::MAX as u64 {
+				// entry itself is small, offset is the only field of zip64 extended information
+				let mut extra = pos + 46 + name_len;
+				while extra + 4 <= pos + 46 + name_len + extra_len {
+					if u16_at(&buf, extra) == 1 { local = u64_at(&buf, extra + 4); }
+					extra += 4 + u16_at(&buf, extra + 2);
+				}
+			}
+			let mut head = read_at(file, local, 30)?;
+			if u32_at(&head, 0) != 0x04034b50 { return Err(invalid()); }
+			seal(&mut head, 4, size);
+			file.seek(io::SeekFrom::Start(local))?;
+			file.write_all(&head)?;
+		}
+		pos += 46 + name_len + extra_len + u16_at(&buf, pos + 32);
+	}
+	file.seek(io::SeekFrom::Start(cd))?;
+	file.write_all(&buf)
+}
+
+/// Zip with single entry of `path`, compressed data of it is copied into final archive as is
+#[cfg(feature = "zip")]
+fn deflate_entry(dir: &Path, item: &Item, mut option: FileOptions, cfg: &CompressConfig, spool: &Path) -> io::Result<Spool> {
+	let Item { path, is_dir, link } = item;
+	let name = entry_name(dir, path);
+	let meta = path.symlink_metadata()?;
+	// entries keep modification time of files so `--update` can tell which ones changed
+	if let Some(mtime) = cfg.mtime(&meta).or_else(|| unix_time(&meta)) {
+		option = option.last_modified_time(zip_time(mtime));
+	}
+	if let Some(link) = link {
+		// zip only has symbolic links, target of alias is made relative to it
+		let target = match link {
+			Link::Symbolic(target) => target.to_string_lossy().replace('\\', "/"),
+			Link::Hard(target) => relative_link(Path::new(&name), target),
+		};
+		let mut zip = ZipWriter::new(Spool::Memory(io::Cursor::default()));
+		zip.add_symlink(name, target, option.compression_method(CompressionMethod::Stored))?;
+		return Ok(zip.finish()?);
+	}
+	if let Some(permissions) = cfg.permissions(*is_dir) {
+		option = option.unix_permissions(permissions);
+	}
+	if *is_dir {
+		let mut zip = ZipWriter::new(Spool::Memory(io::Cursor::default()));
+		zip.add_directory(name, option)?;
+		return Ok(zip.finish()?);
+	}
+	#[cfg(feature = "optimize")]
+	if let Some(optimizer) = &cfg.optimize {
+		if let Some(data) = optimizer.optimized(path, &name)? {
+			return file_entry(&name, io::Cursor::new(data), option, cfg, spool);
+		}
+	}
+	file_entry(&name, File::open(path)?, option, cfg, spool)
+}
+
+/// Single entry zip of file content, compressed with method picked for it
+#[cfg(feature = "zip")]
+fn file_entry(name: &str, mut f: impl Read + Seek, option: FileOptions, cfg: &CompressConfig, spool: &Path) -> io::Result<Spool> {
+	let len = f.seek(io::SeekFrom::End(0))?;
+	let out = Spool::new(spool.to_path_buf(), len)?;
+	f.rewind()?;
+	let mut head = Vec::with_capacity(16);
+	(&mut f).take(16).read_to_end(&mut head)?;
+	f.rewind()?;
+	let option = option.large_file(len >= u32::MAX as u64);
+	let method = cfg.method(name, &head);
+	let mut buf = match method {
+		EntryMethod::Store => return single_entry(name, &mut f, option.compression_method(CompressionMethod::Stored), out),
+		EntryMethod::Deflate => single_entry(name, &mut f, option.compression_method(CompressionMethod::Deflated).compression_level(Some(cfg.preset.deflate())), out)?,
+		EntryMethod::Zstd => single_entry(name, &mut f, option.compression_method(CompressionMethod::Zstd).compression_level(Some(cfg.preset.zstd())), out)?,
+	};
+	// content that doesn't shrink is stored, so it's not inflated on every read
+	if ZipArchive::new(&mut buf)?.by_index_raw(0)?.compressed_size() >= len {
+		f.rewind()?;
+		buf.clear()?;
+		return single_entry(name, &mut f, option.compression_method(CompressionMethod::Stored), buf);
+	}
+	Ok(buf)
+}
+
+/// Encrypt data of single entry zip made by `deflate_entry`
+#[cfg(all(feature = "zip", feature = "encrypt"))]
+fn seal(buf: Spool, spool: PathBuf) -> io::Result<Sealed> {
+	let mut zip = ZipArchive::new(buf)?;
+	let mut entry = zip.by_index_raw(0)?;
+	// sizes of encrypted entries are patched in place, they must fit without zip64
+	if entry.compressed_size() >= u32::MAX as u64 - 64 || entry.size() >= u32::MAX as u64 {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "files of 4 GiB or more can't be encrypted"));
+	}
+	let method = match entry.compression() {
+		CompressionMethod::Deflated => 8,
+		CompressionMethod::Zstd => 93,
+		_ => 0,
+	};
+	let mut payload = Spool::new(spool, entry.compressed_size())?;
+	crate::crypto::encrypt_aes(&mut entry, &mut payload, crate::crypto::passphrase(false)?)?;
+	Ok(Sealed {
+		name: entry.name().to_string(),
+		modified: entry.last_modified(),
+		mode: entry.unix_mode().unwrap_or(0o100644),
+		size: entry.size(),
+		method,
+		payload,
+	})
+}
+
+#[cfg(feature = "zip")]
+fn single_entry(name: &str, content: &mut impl Read, option: FileOptions, out: Spool) -> io::Result<Spool> {
+	let mut zip = ZipWriter::new(out);
+	zip.start_file(name, option)?;
+	io::copy(content, &mut zip)?;
+	Ok(zip.finish()?)
+}
+
+/// Path of `target` relative to folder of `link`, both relative to archive root
+#[cfg(feature = "zip")]
+pub(crate) fn relative_link(link: &Path, target: &Path) -> String {
+	let from: Vec<_> = link.parent().into_iter().flat_map(Path::components).collect();
+	let to: Vec<_> = target.components().collect();
+	let shared = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+	let up = std::iter::repeat_n("..", from.len() - shared).map(String::from);
+	let down = to[shared..].iter().map(|it| it.as_os_str().to_string_lossy().into_owned());
+	up.chain(down).collect::<Vec<_>>().join("/")
+}
+
+/// Zip timestamp of unix `secs`, clamped to range zip can store
+#[cfg(feature = "zip")]
+pub(crate) fn zip_time(secs: u64) -> zip::DateTime {
+	use chrono::{Datelike, Timelike};
+	let time = chrono::DateTime::from_timestamp(secs.max(ZIP_EPOCH) as i64, 0).unwrap_or_default().naive_utc();
+	zip::DateTime::from_date_and_time(time.year().min(2107) as u16, time.month() as u8, time.day() as u8, time.hour() as u8, time.minute() as u8, time.second() as u8)
+		.unwrap_or_default()
+}
+
+/// Unix seconds of zip timestamp, inverse of [`zip_time`]
+#[cfg(feature = "zip")]
+pub(crate) fn unix_secs(time: zip::DateTime) -> Option<u64> {
+	let date = chrono::NaiveDate::from_ymd_opt(time.year() as i32, time.month() as u32, time.day() as u32)?;
+	let time = date.and_hms_opt(time.hour() as u32, time.minute() as u32, time.second() as u32)?;
+	u64::try_from(time.and_utc().timestamp()).ok()
+}
+
+#[cfg(feature = "zip")]
+/// Add files of `dir` that `output` doesn't contain yet to it, written like [`compress_zip`] does with `cfg`:
+/// other names of hard linked content become links and compression method is picked by type. Return number of added files
+pub fn append_zip(dir: &Path, output: &Path, cfg: &CompressConfig) -> io::Result<usize> {
+	let file = OpenOptions::new().read(true).write(true).open(output)?;
+	let mut previous = ZipArchive::new(&file)?;
+	let mut names = HashSet::new();
+	// appended entries need their file type patched like fresh ones, modes of existing entries are written back as they are
+	let mut headers = Vec::with_capacity(previous.len());
+	for index in 0..previous.len() {
+		let entry = previous.by_index_raw(index)?;
+		names.insert(entry.name().to_string());
+		headers.push(Header { mode: entry.unix_mode().unwrap_or(0o100644), sealed: None });
+	}
+	let mut writer = ZipWriter::new_append(file)?;
+	let mut added = 0;
+	for (i, item) in sorted_entries(dir)?.iter().enumerate() {
+		let name = entry_name(dir, &item.path);
+		if !names.insert(if item.is_dir { format!("{name}/") } else { name.clone() }) { continue; }
+		if !item.is_dir {
+			info!("appending {name:?}");
+			added += 1;
+		}
+		let buf = deflate_entry(dir, item, FileOptions::default(), cfg, &spool_path(output, i, "spool")).map_err(at(&item.path))?;
+		let mut zip = ZipArchive::new(buf)?;
+		let entry = zip.by_index_raw(0)?;
+		headers.push(Header { mode: entry.unix_mode().unwrap_or(0o100644), sealed: None });
+		writer.raw_copy_file(entry)?;
+	}
+	let mut file = writer.finish()?;
+	patch_headers(&mut file, &headers)?;
+	file.sync_all()?;
+	Ok(added)
+}
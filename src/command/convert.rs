@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use tracing::{error, info};
+
+use crate::archive::{Archive, Entry};
+use crate::cli::{ConvertFormat, Config};
+use crate::warc::WarcWriter;
+
+/// Interval between progress reports
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+/// Links followed before giving up on a loop
+const MAX_LINKS: usize = 40;
+
+pub(crate) async fn run(cfg: Config) {
+	let Config::Convert { input, output, to, base_url } = cfg else { unreachable!() };
+	let to = to.unwrap_or_else(|| ConvertFormat::of(&output));
+	let res = {
+		let output = output.clone();
+		tokio::task::spawn_blocking(move || convert(&input, &output, to, &base_url)).await.unwrap()
+	};
+	match res {
+		Ok(files) => info!("converted {files} files into {output:?}"),
+		Err(e) => {
+			error!("failed to convert into {output:?}: {e}");
+			std::process::exit(1);
+		}
+	}
+}
+
+/// Copy every file of archive at `input` into new `output` of format `to`, one file at a time.
+/// Links are written after content they point to and stay links where format can hold them.
+/// Partially written output file is removed on error
+pub fn convert(input: &str, output: &Path, to: ConvertFormat, base_url: &str) -> io::Result<usize> {
+	let source = Archive::open(input)?;
+	let res = open(output, to, base_url).and_then(|mut sink| {
+		let entries = source.entries();
+		let files: HashMap<&str, &Entry> = entries.iter().map(|it| (it.path.as_str(), it)).collect();
+		let (links, contents): (Vec<&Entry>, Vec<&Entry>) = entries.iter().partition(|it| target(&files, it).is_some());
+		let mut last = Instant::now();
+		for (i, entry) in contents.into_iter().chain(links).enumerate() {
+			let linked = match target(&files, entry) {
+				Some(target) => sink.link(entry, target)?,
+				None => false,
+			};
+			if !linked {
+				let content = source.read(&entry.path).map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", entry.path)))?;
+				sink.add(entry, &content)?;
+			}
+			if last.elapsed() >= PROGRESS_INTERVAL {
+				last = Instant::now();
+				info!("converted {}/{} files", i + 1, entries.len());
+			}
+		}
+		sink.finish()?;
+		Ok(entries.len())
+	});
+	if res.is_err() && to != ConvertFormat::Dir {
+		let _ = std::fs::remove_file(output);
+	}
+	res
+}
+
+/// File of archive that `entry` end up linking to, `None` when it isn't a link or its target isn't in archive
+fn target<'a>(files: &HashMap<&str, &'a Entry>, entry: &Entry) -> Option<&'a str> {
+	let mut target = files.get(entry.link.as_deref()?)?;
+	for _ in 0..MAX_LINKS {
+		match &target.link {
+			Some(link) => target = files.get(link.as_str())?,
+			None => return Some(&target.path),
+		}
+	}
+	None
+}
+
+/// Destination of `convert`
+trait Sink {
+	fn add(&mut self, entry: &Entry, content: &[u8]) -> io::Result<()>;
+	/// Store `entry` as link to `target` added before, `false` when format can't hold links and content is copied instead
+	fn link(&mut self, _entry: &Entry, _target: &str) -> io::Result<bool> {
+		Ok(false)
+	}
+	fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+fn open(output: &Path, to: ConvertFormat, base_url: &str) -> io::Result<Box<dyn Sink>> {
+	Ok(match to {
+		ConvertFormat::Dir => {
+			std::fs::create_dir_all(output)?;
+			Box::new(DirSink(output.to_path_buf()))
+		}
+		#[cfg(feature = "zip")]
+		ConvertFormat::Zip => Box::new(ZipSink { zip: zip::ZipWriter::new(File::create(output)?), dirs: Default::default() }),
+		#[cfg(feature = "tar-archive")]
+		ConvertFormat::TarZst => {
+			let jobs = std::thread::available_parallelism().map_or(1, |it| it.get());
+			let writer = crate::seekable::SeekableWriter::new(File::create(output)?, crate::command::compress::Preset::Default.zstd(), jobs);
+			Box::new(tar::Builder::new(writer))
+		}
+		#[cfg(feature = "sqlite")]
+		ConvertFormat::Sqlite => {
+			if output.exists() {
+				std::fs::remove_file(output)?;
+			}
+			let db = rusqlite::Connection::open(output).map_err(io::Error::other)?;
+			db.execute_batch(crate::archive::SQLITE_SCHEMA).map_err(io::Error::other)?;
+			// single transaction, otherwise every insert is synced to disk
+			db.execute_batch("BEGIN").map_err(io::Error::other)?;
+			Box::new(SqliteSink(db))
+		}
+		ConvertFormat::Warc => Box::new(WarcWriter::new(BufWriter::new(File::create(output)?), base_url)?),
+	})
+}
+
+#[cfg(any(feature = "zip", feature = "tar-archive", feature = "sqlite"))]
+fn unix_secs(modified: Option<std::time::SystemTime>) -> Option<u64> {
+	Some(modified?.duration_since(std::time::UNIX_EPOCH).ok()?.as_secs())
+}
+
+struct DirSink(PathBuf);
+
+impl DirSink {
+	/// Where `entry` is written, with its parent folders created
+	fn path(&self, entry: &Entry) -> io::Result<PathBuf> {
+		if !Path::new(&entry.path).components().all(|it| matches!(it, Component::Normal(_))) {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?} point outside of archive", entry.path)));
+		}
+		let path = self.0.join(&entry.path);
+		if let Some(parent) = path.parent() {
+			std::fs::create_dir_all(parent)?;
+		}
+		Ok(path)
+	}
+}
+
+impl Sink for DirSink {
+	fn add(&mut self, entry: &Entry, content: &[u8]) -> io::Result<()> {
+		let path = self.path(entry)?;
+		std::fs::write(&path, content)?;
+		if let Some(modified) = entry.modified {
+			File::options().write(true).open(&path)?.set_modified(modified)?;
+		}
+		Ok(())
+	}
+
+	/// Hard link, like forward store aliases
+	fn link(&mut self, entry: &Entry, target: &str) -> io::Result<bool> {
+		let path = self.path(entry)?;
+		if path.symlink_metadata().is_ok() {
+			std::fs::remove_file(&path)?;
+		}
+		std::fs::hard_link(self.0.join(target), &path)?;
+		Ok(true)
+	}
+
+	fn finish(self: Box<Self>) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Zip with folder entries before their content, as `serve` expect
+#[cfg(feature = "zip")]
+struct ZipSink {
+	zip: zip::ZipWriter<File>,
+	dirs: std::collections::HashSet<String>,
+}
+
+#[cfg(feature = "zip")]
+impl ZipSink {
+	/// Options of `entry`, folders it's in are added first
+	fn options(&mut self, entry: &Entry, len: u64) -> io::Result<zip::write::FileOptions> {
+		let mut option = zip::write::FileOptions::default().large_file(len >= u32::MAX as u64);
+		if let Some(secs) = unix_secs(entry.modified) {
+			option = option.last_modified_time(crate::command::compress::zip_time(secs));
+		}
+		for (i, _) in entry.path.match_indices('/') {
+			if self.dirs.insert(entry.path[..i].to_string()) {
+				self.zip.add_directory(&entry.path[..i], option)?;
+			}
+		}
+		Ok(option)
+	}
+}
+
+#[cfg(feature = "zip")]
+impl Sink for ZipSink {
+	fn add(&mut self, entry: &Entry, content: &[u8]) -> io::Result<()> {
+		use std::io::Write;
+		let option = self.options(entry, content.len() as u64)?;
+		self.zip.start_file(&entry.path, option)?;
+		self.zip.write_all(content)
+	}
+
+	/// Symbolic link relative to folder of `entry`, like compress store aliases
+	fn link(&mut self, entry: &Entry, target: &str) -> io::Result<bool> {
+		let option = self.options(entry, 0)?;
+		let target = crate::command::compress::relative_link(Path::new(&entry.path), Path::new(target));
+		self.zip.add_symlink(&entry.path, target, option)?;
+		Ok(true)
+	}
+
+	fn finish(mut self: Box<Self>) -> io::Result<()> {
+		self.zip.finish()?.sync_all()
+	}
+}
+
+#[cfg(feature = "tar-archive")]
+impl Sink for tar::Builder<crate::seekable::SeekableWriter<File>> {
+	fn add(&mut self, entry: &Entry, content: &[u8]) -> io::Result<()> {
+		let mut header = tar::Header::new_gnu();
+		header.set_size(content.len() as u64);
+		header.set_mode(0o644);
+		header.set_mtime(unix_secs(entry.modified).unwrap_or_default());
+		self.append_data(&mut header, &entry.path, content)
+	}
+
+	fn link(&mut self, entry: &Entry, target: &str) -> io::Result<bool> {
+		let mut header = tar::Header::new_gnu();
+		header.set_entry_type(tar::EntryType::Link);
+		header.set_size(0);
+		header.set_mode(0o644);
+		header.set_mtime(unix_secs(entry.modified).unwrap_or_default());
+		self.append_link(&mut header, &entry.path, target)?;
+		Ok(true)
+	}
+
+	fn finish(self: Box<Self>) -> io::Result<()> {
+		self.into_inner()?.finish()?.sync_all()
+	}
+}
+
+#[cfg(feature = "sqlite")]
+struct SqliteSink(rusqlite::Connection);
+
+#[cfg(feature = "sqlite")]
+impl Sink for SqliteSink {
+	fn add(&mut self, entry: &Entry, content: &[u8]) -> io::Result<()> {
+		self.0.execute("INSERT OR REPLACE INTO resource (path, modified, content) VALUES (?1, ?2, ?3)", rusqlite::params![entry.path, unix_secs(entry.modified), content])
+			.map(|_| ())
+			.map_err(io::Error::other)
+	}
+
+	fn finish(self: Box<Self>) -> io::Result<()> {
+		self.0.execute_batch("COMMIT").map_err(io::Error::other)
+	}
+}
+
+impl Sink for WarcWriter<BufWriter<File>> {
+	fn add(&mut self, entry: &Entry, content: &[u8]) -> io::Result<()> {
+		WarcWriter::add(self, &entry.path, entry.modified, content)
+	}
+
+	fn finish(self: Box<Self>) -> io::Result<()> {
+		WarcWriter::finish(*self)?.into_inner().map_err(|e| e.into_error())?.sync_all()
+	}
+}
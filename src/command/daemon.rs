@@ -0,0 +1,118 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::Local;
+use cron::Schedule;
+use serde::Deserialize;
+use tokio::time::sleep;
+use tracing::{error, info};
+
+use crate::Config;
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+use crate::cli::CompressFormat;
+use crate::command::forward::{crawl, CrawlConfig};
+
+#[derive(Deserialize)]
+struct DaemonConfig {
+	site: Vec<Site>,
+}
+
+#[derive(Deserialize, Clone)]
+struct Site {
+	/// upstream host
+	host: String,
+	/// output dir
+	output: PathBuf,
+	/// cron expression with seconds field (eg. `0 0 3 * * *` for every day at 3 AM)
+	schedule: String,
+	#[serde(default = "default_secure")]
+	secure: bool,
+	/// paths to start crawling from
+	#[serde(default = "default_seeds")]
+	seeds: Vec<String>,
+	/// only follow links under these paths
+	#[serde(default)]
+	scope: Vec<String>,
+	/// host that absolute links will be rewritten into
+	#[serde(default = "default_rewrite")]
+	rewrite: String,
+	#[serde(default)]
+	prefix_local: Option<String>,
+	/// maximum resources to fetch per run
+	#[serde(default = "default_limit")]
+	limit: usize,
+	/// compress output dir after every run
+	#[cfg(any(feature = "zip", feature = "tar-archive"))]
+	#[serde(default)]
+	compress: Option<CompressFormat>,
+	/// compressed file name, default to name of output dir
+	#[cfg(any(feature = "zip", feature = "tar-archive"))]
+	#[serde(default)]
+	compress_output: Option<String>,
+}
+
+fn default_secure() -> bool { true }
+
+fn default_seeds() -> Vec<String> { vec![String::from("/")] }
+
+// default port of serve command
+fn default_rewrite() -> String { String::from("localhost:3000") }
+
+fn default_limit() -> usize { 10000 }
+
+pub(crate) async fn run(cfg: Config) {
+	let Config::Daemon { config, now } = cfg else { unreachable!() };
+	let content = std::fs::read_to_string(&config).expect("Read daemon config");
+	let DaemonConfig { site } = toml::from_str(&content).expect("Parse daemon config");
+	let mut tasks = vec![];
+	for site in site {
+		let schedule = Schedule::from_str(&site.schedule).unwrap_or_else(|e| panic!("Invalid schedule of {}: {e}", site.host));
+		tasks.push(tokio::spawn(watch_schedule(site, schedule, now)));
+	}
+	for task in tasks {
+		if let Err(e) = task.await {
+			error!("{e}");
+		}
+	}
+}
+
+async fn watch_schedule(site: Site, schedule: Schedule, now: bool) {
+	if now {
+		archive(site.clone()).await;
+	}
+	while let Some(next) = schedule.upcoming(Local).next() {
+		info!("next archive of {} at {next}", site.host);
+		let wait = (next - Local::now()).to_std().unwrap_or(Duration::ZERO);
+		sleep(wait).await;
+		archive(site.clone()).await;
+	}
+}
+
+async fn archive(site: Site) {
+	info!("archiving {} into {:?}", site.host, site.output);
+	let fetched = crawl(CrawlConfig {
+		secure: site.secure,
+		host: site.host.clone(),
+		output: site.output.clone(),
+		rewrite: site.rewrite,
+		prefix_local: site.prefix_local,
+		seeds: site.seeds,
+		scope: site.scope,
+		limit: site.limit,
+	}).await;
+	info!("fetched {fetched} resources of {}", site.host);
+
+	#[cfg(any(feature = "zip", feature = "tar-archive"))]
+	if let Some(format) = site.compress {
+		let output = crate::command::compress::output_path(&site.output, &format, site.compress_output);
+		let res = tokio::task::spawn_blocking(move || {
+			crate::command::compress::compress(&site.output, &format, &output, &Default::default()).map(|_| output)
+		}).await;
+		match res {
+			Ok(Ok(output)) => info!("finished compress {output:?}"),
+			Ok(Err(e)) => error!("failed to compress {}: {e}", site.host),
+			Err(e) => error!("failed to compress {}: {e}", site.host),
+		}
+	}
+}
@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use similar::TextDiff;
+use tracing::error;
+
+use crate::archive::{Archive, Entry};
+use crate::Config;
+use crate::search::INDEX_FILE;
+
+#[derive(Serialize, Default)]
+struct Report {
+	added: Vec<Resource>,
+	removed: Vec<Resource>,
+	changed: Vec<Changed>,
+	/// resources that couldn't be read from one of archives
+	errors: Vec<Failed>,
+}
+
+#[derive(Serialize)]
+struct Resource {
+	path: String,
+	size: u64,
+}
+
+#[derive(Serialize)]
+struct Changed {
+	path: String,
+	old_size: u64,
+	new_size: u64,
+	delta: i64,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	diff: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Failed {
+	path: String,
+	error: String,
+}
+
+pub(crate) async fn run(cfg: Config) {
+	let Config::Diff { old, new, json, quiet } = cfg else { unreachable!() };
+	let report = tokio::task::spawn_blocking(move || {
+		let open = |spec: &str| Archive::open(spec).map_err(|e| format!("failed to open {spec:?}: {e}"));
+		Ok::<_, String>(diff(&open(&old)?, &open(&new)?, !quiet))
+	}).await.unwrap();
+	let report = match report {
+		Ok(it) => it,
+		Err(e) => {
+			error!("{e}");
+			std::process::exit(1);
+		}
+	};
+
+	if json {
+		println!("{}", serde_json::to_string_pretty(&report).unwrap());
+		return;
+	}
+	for it in &report.added {
+		println!("+ {} ({} bytes)", it.path, it.size);
+	}
+	for it in &report.removed {
+		println!("- {} ({} bytes)", it.path, it.size);
+	}
+	for it in &report.changed {
+		println!("~ {} ({} -> {} bytes, {:+})", it.path, it.old_size, it.new_size, it.delta);
+		if let Some(diff) = &it.diff {
+			println!("{diff}");
+		}
+	}
+	for it in &report.errors {
+		println!("! {} ({})", it.path, it.error);
+	}
+	println!("{} added, {} removed, {} changed, {} failed", report.added.len(), report.removed.len(), report.changed.len(), report.errors.len());
+}
+
+fn diff(old: &Archive, new: &Archive, with_content: bool) -> Report {
+	let mut report = Report::default();
+	// search index is rebuilt from content, it isn't a resource of site
+	let entries = |archive: &Archive| -> BTreeMap<String, Entry> {
+		archive.entries().into_iter().filter(|it| it.path != INDEX_FILE).map(|it| (it.path.clone(), it)).collect()
+	};
+	let old_entries = entries(old);
+	let mut new_entries = entries(new);
+	for (path, entry) in old_entries {
+		let Some(updated) = new_entries.remove(&path) else {
+			report.removed.push(Resource { path, size: entry.size });
+			continue;
+		};
+		let (before, after) = match (old.read(&path), new.read(&path)) {
+			(Ok(before), Ok(after)) => (before, after),
+			(Err(e), _) | (_, Err(e)) => {
+				report.errors.push(Failed { path, error: e.to_string() });
+				continue;
+			}
+		};
+		if before == after { continue; }
+		let diff = (with_content && is_text(&path)).then(|| {
+			let (before, after) = (String::from_utf8_lossy(&before), String::from_utf8_lossy(&after));
+			TextDiff::from_lines(&before, &after)
+				.unified_diff()
+				.context_radius(2)
+				.header(&format!("a/{path}"), &format!("b/{path}"))
+				.to_string()
+		});
+		report.changed.push(Changed {
+			delta: updated.size as i64 - entry.size as i64,
+			path,
+			old_size: entry.size,
+			new_size: updated.size,
+			diff,
+		});
+	}
+	report.added = new_entries.into_values().map(|it| Resource { path: it.path, size: it.size }).collect();
+	report
+}
+
+/// Resource that worth showing line diff (html, css, js, json...)
+fn is_text(path: &str) -> bool {
+	mime_guess::from_path(path).first().is_some_and(|it| {
+		it.type_() == mime_guess::mime::TEXT || matches!(it.subtype().as_str(), "javascript" | "json" | "xml" | "xhtml+xml")
+	})
+}
@@ -0,0 +1,235 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek};
+#[cfg(feature = "zip")]
+use std::path::Component;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "zip")]
+use std::time::{Duration, SystemTime};
+
+use tracing::{error, info};
+#[cfg(feature = "zip")]
+use zip::ZipArchive;
+
+use crate::cli::Config;
+use crate::command::compress::at;
+#[cfg(feature = "zip")]
+use crate::command::compress::unix_secs;
+use crate::volume::{archive_name, VolumeReader, INDEX_EXT};
+
+pub(crate) async fn run(cfg: Config) {
+	#[cfg(feature = "encrypt")]
+	if let Config::Extract { key_file: Some(key_file), .. } = &cfg {
+		crate::crypto::use_key_file(key_file.clone());
+	}
+	let Config::Extract { archive, output, .. } = cfg else { unreachable!() };
+	let output = output.unwrap_or_else(|| default_output(&archive));
+	let res = {
+		let output = output.clone();
+		tokio::task::spawn_blocking(move || extract(&archive, &output)).await.unwrap()
+	};
+	match res {
+		Ok(()) => info!("finished extract into {output:?}"),
+		Err(e) => {
+			error!("failed to extract into {output:?}: {e}");
+			std::process::exit(1);
+		}
+	}
+}
+
+/// Name of archive without extension
+fn default_output(archive: &Path) -> PathBuf {
+	let name = archive.file_name().map(|it| it.to_string_lossy()).unwrap_or_default();
+	let name = name.strip_suffix(INDEX_EXT).unwrap_or(&name);
+	let name = name.strip_suffix(".age").unwrap_or(name);
+	let stem = [".tar.gz", ".tar.zst", ".tgz", ".tar", ".zip"].iter().find_map(|it| name.strip_suffix(it)).unwrap_or(name);
+	PathBuf::from(stem)
+}
+
+/// Unpack `archive` into `output` folder, kind of archive is told by its extension.
+/// Split archive is read through its index
+pub fn extract(archive: &Path, output: &Path) -> io::Result<()> {
+	match archive_name(archive) {
+		Some(name) => extract_from(&name, VolumeReader::open(archive)?, output),
+		None => extract_from(&archive.to_string_lossy(), File::open(archive)?, output),
+	}
+}
+
+fn extract_from(name: &str, reader: impl Read + Seek, output: &Path) -> io::Result<()> {
+	#[cfg(feature = "tar-archive")]
+	{
+		#[cfg(feature = "encrypt")]
+		if let Some(name) = name.strip_suffix(".age") {
+			let Some(ext) = tar_ext(name) else {
+				return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{name:?} is not an encrypted tar archive")));
+			};
+			return extract_tar(ext, crate::crypto::age_reader(io::BufReader::new(reader))?, output);
+		}
+		if let Some(ext) = tar_ext(name) {
+			return extract_tar(ext, reader, output);
+		}
+	}
+	#[cfg(not(feature = "tar-archive"))]
+	let _ = name;
+	#[cfg(feature = "zip")]
+	return extract_zip(reader, output);
+	#[cfg(not(feature = "zip"))]
+	Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{name:?} is not a tar archive")))
+}
+
+/// Extension of tar `name` that tell how it's compressed
+#[cfg(feature = "tar-archive")]
+fn tar_ext(name: &str) -> Option<&'static str> {
+	[".tar", ".tar.gz", ".tgz", ".tar.zst"].into_iter().find(|it| name.ends_with(it))
+}
+
+#[cfg(feature = "tar-archive")]
+fn extract_tar(ext: &str, reader: impl Read, output: &Path) -> io::Result<()> {
+	match ext {
+		".tar.gz" | ".tgz" => unpack_tar(flate2::read::GzDecoder::new(reader), output),
+		// seek table of seekable zstd is skippable frame, so plain decoder read it too
+		".tar.zst" => unpack_tar(zstd::Decoder::new(reader)?, output),
+		_ => unpack_tar(reader, output),
+	}
+}
+
+/// Tar keep hard links, symbolic links, permissions and timestamps itself and refuse paths outside of `output`
+#[cfg(feature = "tar-archive")]
+fn unpack_tar(reader: impl Read, output: &Path) -> io::Result<()> {
+	let mut tar = tar::Archive::new(reader);
+	tar.set_preserve_mtime(true);
+	tar.set_preserve_permissions(true);
+	tar.set_overwrite(true);
+	std::fs::create_dir_all(output).map_err(at(output))?;
+	tar.unpack(output)
+}
+
+#[cfg(feature = "zip")]
+fn extract_zip<R: Read + Seek>(reader: R, output: &Path) -> io::Result<()> {
+	let mut zip = ZipArchive::new(reader)?;
+	let mut dirs = vec![];
+	std::fs::create_dir_all(output).map_err(at(output))?;
+	for i in 0..zip.len() {
+		let mut entry = match entry_passphrase(&mut zip, i)? {
+			Some(passphrase) => zip.by_index_decrypt(i, passphrase.as_bytes())?
+				.map_err(|_| io::Error::new(io::ErrorKind::PermissionDenied, "wrong passphrase"))?,
+			None => zip.by_index(i)?,
+		};
+		let Some(name) = entry.enclosed_name().map(Path::to_path_buf) else {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?} point outside of archive", entry.name())));
+		};
+		let path = output.join(&name);
+		through_link(output, &name)?;
+		let modified = unix_secs(entry.last_modified()).map(|it| SystemTime::UNIX_EPOCH + Duration::from_secs(it));
+		let mode = entry.unix_mode();
+		let is_link = path.symlink_metadata().is_ok_and(|it| it.file_type().is_symlink());
+		if entry.is_dir() {
+			if is_link {
+				return Err(io::Error::new(io::ErrorKind::InvalidData, format!("folder {name:?} is a link extracted earlier")));
+			}
+			std::fs::create_dir_all(&path).map_err(at(&path))?;
+			dirs.push((path, modified, mode));
+			continue;
+		}
+		if let Some(parent) = path.parent() {
+			std::fs::create_dir_all(parent).map_err(at(parent))?;
+		}
+		if mode.is_some_and(|it| it & 0o170000 == 0o120000) {
+			let target = io::read_to_string(&mut entry)?;
+			if escapes(output, &name, &target) {
+				return Err(io::Error::new(io::ErrorKind::InvalidData, format!("link {name:?} point outside of archive")));
+			}
+			let _ = std::fs::remove_file(&path);
+			symlink(&target, &path).map_err(at(&path))?;
+			continue;
+		}
+		// link extracted earlier is replaced, not followed
+		if is_link {
+			std::fs::remove_file(&path).map_err(at(&path))?;
+		}
+		let mut file = File::create(&path).map_err(at(&path))?;
+		io::copy(&mut entry, &mut file).map_err(at(&path))?;
+		restore(&file, modified, mode).map_err(at(&path))?;
+	}
+	// writing files touch their folder, so folders are restored last and deepest first
+	for (path, modified, mode) in dirs.into_iter().rev() {
+		restore(&File::open(&path)?, modified, mode).map_err(at(&path))?;
+	}
+	Ok(())
+}
+
+/// Passphrase of entry `i` when it's encrypted
+#[cfg(feature = "zip")]
+fn entry_passphrase<R: Read + Seek>(zip: &mut ZipArchive<R>, i: usize) -> io::Result<Option<&'static str>> {
+	#[cfg(feature = "encrypt")]
+	if matches!(zip.by_index(i), Err(zip::result::ZipError::UnsupportedArchive(zip::result::ZipError::PASSWORD_REQUIRED))) {
+		return crate::crypto::passphrase(false).map(Some);
+	}
+	let _ = (zip, i);
+	Ok(None)
+}
+
+/// Put back modification time and permissions of extracted entry
+#[cfg(feature = "zip")]
+fn restore(file: &File, modified: Option<SystemTime>, mode: Option<u32>) -> io::Result<()> {
+	if let Some(modified) = modified {
+		file.set_modified(modified)?;
+	}
+	#[cfg(unix)]
+	if let Some(mode) = mode {
+		use std::os::unix::fs::PermissionsExt;
+		file.set_permissions(std::fs::Permissions::from_mode(mode & 0o7777))?;
+	}
+	#[cfg(not(unix))]
+	let _ = mode;
+	Ok(())
+}
+
+#[cfg(all(feature = "zip", unix))]
+fn symlink(target: &str, path: &Path) -> io::Result<()> {
+	std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(all(feature = "zip", windows))]
+fn symlink(target: &str, path: &Path) -> io::Result<()> {
+	std::os::windows::fs::symlink_file(target, path)
+}
+
+/// Refuse entry `name` whose folders under `output` include a link extracted earlier, writing through it could reach anywhere
+#[cfg(feature = "zip")]
+fn through_link(output: &Path, name: &Path) -> io::Result<()> {
+	let mut path = output.to_path_buf();
+	for it in name.parent().into_iter().flat_map(Path::components) {
+		path.push(it);
+		match path.symlink_metadata() {
+			Ok(meta) if meta.file_type().is_symlink() => {
+				return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{name:?} is inside of link {path:?}")));
+			}
+			Ok(_) => {}
+			// deeper folders don't exist either
+			Err(_) => break,
+		}
+	}
+	Ok(())
+}
+
+/// Whether link at `name` resolve to somewhere outside of archive root under `output`.
+/// `..` only goes up from folders that exist and aren't links, otherwise where it lands depends on what is extracted later
+#[cfg(feature = "zip")]
+fn escapes(output: &Path, name: &Path, target: &str) -> bool {
+	let mut path: Vec<_> = name.parent().into_iter().flat_map(Path::components).collect();
+	for it in Path::new(target).components() {
+		match it {
+			Component::Normal(_) => path.push(it),
+			Component::ParentDir => {
+				let folder = output.join(path.iter().collect::<PathBuf>());
+				if path.pop().is_none() || !folder.symlink_metadata().is_ok_and(|it| it.is_dir()) {
+					return true;
+				}
+			}
+			Component::CurDir => {}
+			Component::RootDir | Component::Prefix(_) => return true,
+		}
+	}
+	false
+}
@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+use futures_util::StreamExt;
+use tracing::{error, info, warn};
+
+use crate::Config;
+use crate::command::forward::{ForwardConfig, refetch};
+
+pub(crate) async fn run(cfg: Config) {
+	let Config::Fill { secure, host, path, log, rewrite, prefix_local } = cfg else { unreachable!() };
+	let content = std::fs::read_to_string(&log).expect("Read miss log");
+	let mut seen = HashSet::new();
+	let urls: Vec<String> = content.lines()
+		.map(str::trim)
+		.filter(|it| !it.is_empty() && seen.insert(*it))
+		.map(str::to_string)
+		.collect();
+
+	// resources of zip are fetched into folder next to it and appended once every fetch is done
+	let is_zip = path.extension().is_some_and(|it| it == "zip");
+	let output = if is_zip {
+		let mut dir = path.clone().into_os_string();
+		dir.push(".fill");
+		dir.into()
+	} else {
+		path.clone()
+	};
+	let archived = match is_zip.then(|| zip_names(&path)).transpose() {
+		Ok(it) => it.unwrap_or_default(),
+		Err(e) => {
+			error!("failed to read {path:?}: {e}");
+			std::process::exit(1);
+		}
+	};
+
+	let upstream = ForwardConfig::upstream(secure, host, output.clone(), rewrite, prefix_local, false);
+	let results: Vec<(String, bool)> = futures_util::stream::iter(urls)
+		.map(|url| fill(Arc::clone(&upstream), &output, &archived, url))
+		.buffer_unordered(8)
+		.collect()
+		.await;
+	let missing: Vec<_> = results.iter().filter(|(_, ok)| !ok).map(|(url, _)| url.as_str()).collect();
+	info!("filled {} of {} missing resources", results.len() - missing.len(), results.len());
+
+	if is_zip && output.exists() {
+		append(&path, &output).await;
+	}
+	let mut remaining = missing.join("\n");
+	if !remaining.is_empty() { remaining.push('\n'); }
+	std::fs::write(&log, remaining).expect("Write miss log");
+}
+
+/// Fetch `url` into archive, return whether it is archived afterward. Failed fetch leave nothing behind so it's retried next run
+async fn fill(upstream: Arc<ForwardConfig>, output: &Path, archived: &HashSet<String>, url: String) -> (String, bool) {
+	let Some((path, state, npath)) = upstream.archived_path(&url) else {
+		warn!("{url:?} is not a path of upstream");
+		return (url, false);
+	};
+	let key = npath.strip_prefix(output).unwrap_or(&npath).to_string_lossy().replace('\\', "/");
+	if npath.is_file() || archived.contains(&key) {
+		info!("{url:?} already archived");
+		return (url, true);
+	}
+	let status = refetch(&upstream, path, state).await;
+	if !status.is_success() {
+		warn!("{url:?} responded with {status}");
+	}
+	(url, status.is_success())
+}
+
+#[cfg(feature = "zip")]
+fn zip_names(path: &Path) -> std::io::Result<HashSet<String>> {
+	let zip = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+	Ok(zip.file_names().map(str::to_string).collect())
+}
+
+#[cfg(not(feature = "zip"))]
+fn zip_names(_: &Path) -> std::io::Result<HashSet<String>> {
+	Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "filling zip file need `zip` feature"))
+}
+
+/// Append fetched resources to zip and remove folder they were fetched into
+#[cfg(feature = "zip")]
+async fn append(zip: &Path, dir: &Path) {
+	let (zip, dir) = (zip.to_path_buf(), dir.to_path_buf());
+	let appended = tokio::task::spawn_blocking(move || {
+		let appended = super::compress::append_zip(&dir, &zip, &Default::default());
+		if appended.is_ok() {
+			std::fs::remove_dir_all(&dir)?;
+		}
+		appended
+	}).await.unwrap();
+	match appended {
+		Ok(n) => info!("appended {n} files to zip"),
+		Err(e) => warn!("{e} while appending to zip"),
+	}
+}
+
+#[cfg(not(feature = "zip"))]
+async fn append(_: &Path, _: &Path) {}
@@ -1,42 +1,184 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::hard_link;
+use std::future::Future;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 
-use axum::body::Bytes;
+use axum::body::{Body, Bytes, HttpBody};
 use axum::Extension;
 use axum::extract::{Path, Query, RawBody};
-use axum::http::{HeaderMap, HeaderValue, Method};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
 use axum::http::header::{ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_LENGTH, CONTENT_TYPE, STRICT_TRANSPORT_SECURITY};
 use axum::response::Response;
 use bstr::ByteSlice;
 use futures_util::stream::unfold;
 use futures_util::StreamExt;
-use reqwest::Client;
-use tokio::fs::{create_dir_all, File, write};
+use percent_encoding::percent_decode_str;
+use reqwest::{Client, Url};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use tokio::fs::{create_dir_all, File, rename, write};
 use tokio::io::AsyncWriteExt;
 use tracing::{info, warn};
 
 use crate::{Config, http_all, unwrap_void};
-use crate::cli::HttpConfig;
+use crate::cli::{HttpConfig, TtlRule};
 use crate::common::{normalize_url_path, serve_file, StreamBodyExt, StreamResponse};
+use crate::links::{css_subresources, html_links, html_subresources};
 use crate::state::HttpState;
+use crate::utils::wildcard_match;
 
-struct ForwardConfig {
+/// Background captures that run at once, like pages fetched at once by crawl
+const CAPTURE_JOBS: usize = 8;
+
+pub(crate) struct ForwardConfig {
 	secure: bool,
 	host: String,
 	output: PathBuf,
 	http: HttpConfig,
 	prefix_local: Option<String>,
+	eager: bool,
+	ttl: Vec<TtlRule>,
+	/// paths that are being captured in background
+	capturing: Mutex<HashSet<PathBuf>>,
+	/// bound captures running in background to [`CAPTURE_JOBS`]
+	captures: tokio::sync::Semaphore,
+	/// shared so connections to upstream are reused
+	client: Client,
+	/// archived copy is left untouched, modification time included, when upstream send same content
+	keep_unchanged: bool,
+}
+
+impl ForwardConfig {
+	/// Config to fetch from upstream without listening
+	pub(crate) fn upstream(secure: bool, host: String, output: PathBuf, rewrite: String, prefix_local: Option<String>, keep_unchanged: bool) -> Arc<Self> {
+		Arc::new(Self {
+			secure,
+			host,
+			output,
+			http: HttpConfig { listen: 0, rewrite: Some(rewrite) },
+			prefix_local,
+			eager: false,
+			ttl: vec![],
+			capturing: Default::default(),
+			captures: tokio::sync::Semaphore::new(CAPTURE_JOBS),
+			client: Client::new(),
+			keep_unchanged,
+		})
+	}
+
+	/// Path in archive that `url` (path with optional query) will be stored at
+	pub(crate) fn archived_path(&self, url: &str) -> Option<(String, HttpState, PathBuf)> {
+		let (path, state) = self.resolve(&self.url("")?, url)?;
+		let npath = normalize_url_path(&self.output, &state, &path, self.prefix_local.is_none());
+		Some((path, state, npath))
+	}
+
+	/// Upstream url of `path`
+	fn url(&self, path: &str) -> Option<Url> {
+		let scheme = if self.secure { "https" } else { "http" };
+		Url::parse(&format!("{scheme}://{}/{path}", self.host)).ok()
+	}
+
+	/// Resolve reference found in `base` into request path and state,
+	/// `None` if it point outside of upstream (links that were rewritten to local host count as upstream)
+	fn resolve(&self, base: &Url, r: &str) -> Option<(String, HttpState)> {
+		let r = r.trim();
+		if r.is_empty() || r.starts_with('#') || r.starts_with("data:") { return None; }
+		let url = base.join(r).ok()?;
+		if !matches!(url.scheme(), "http" | "https") { return None; }
+		let authority = match url.port() {
+			Some(port) => format!("{}:{port}", url.host_str()?),
+			None => url.host_str()?.to_string(),
+		};
+		if authority != self.host && Some(&authority) != self.http.rewrite.as_ref() { return None; }
+		let path = percent_decode_str(url.path().trim_start_matches('/')).decode_utf8_lossy().into_owned();
+		let state = HttpState {
+			query: url.query_pairs().into_owned().collect(),
+			method: Method::GET,
+		};
+		Some((path, state))
+	}
+
+	/// Archived copy of `path` is older than first ttl rule that match it
+	fn is_stale(&self, path: &str, npath: &std::path::Path) -> bool {
+		let Some(TtlRule { ttl: Some(ttl), .. }) = self.ttl.iter().find(|it| wildcard_match(&it.pattern, path)) else { return false; };
+		npath.metadata()
+			.and_then(|it| it.modified())
+			.ok()
+			.and_then(|it| it.elapsed().ok())
+			.is_some_and(|age| age > *ttl)
+	}
+}
+
+/// Response is written into temporary file first then renamed into place,
+/// so archived copy is replaced atomically and never left half written.
+/// Temporary file is removed when response isn't committed, eg. client went away
+struct Staged {
+	tmp: PathBuf,
+	out: PathBuf,
+	link: Option<PathBuf>,
+	committed: bool,
+	/// leave archived copy as it is when it hold same content
+	keep_unchanged: bool,
+}
+
+impl Staged {
+	fn new(out: &std::path::Path, link: Option<&std::path::Path>, keep_unchanged: bool) -> Self {
+		let suffix: String = rand::thread_rng()
+			.sample_iter(Alphanumeric)
+			.take(8)
+			.map(char::from)
+			.collect();
+		let mut name = std::ffi::OsString::from(".");
+		name.push(out.file_name().unwrap_or_default());
+		name.push(format!(".{suffix}.part"));
+		Self {
+			tmp: out.with_file_name(name),
+			out: out.to_path_buf(),
+			link: link.map(std::path::Path::to_path_buf),
+			committed: false,
+			keep_unchanged,
+		}
+	}
+
+	async fn commit(mut self) {
+		// dropped staged file is removed
+		if self.keep_unchanged && same_content(&self.tmp, &self.out).await { return; }
+		unwrap_void!(rename(&self.tmp, &self.out).await);
+		self.committed = true;
+		if let Some(link) = &self.link {
+			// old link still point to previous content, new one is made aside and renamed over it
+			unwrap_void!(hard_link(&self.out, &self.tmp));
+			unwrap_void!(rename(&self.tmp, link).await);
+		}
+	}
+}
+
+/// Both files exist and hold same bytes
+async fn same_content(a: &std::path::Path, b: &std::path::Path) -> bool {
+	match (tokio::fs::read(a).await, tokio::fs::read(b).await) {
+		(Ok(a), Ok(b)) => a == b,
+		_ => false,
+	}
+}
+
+impl Drop for Staged {
+	fn drop(&mut self) {
+		if !self.committed {
+			let _ = std::fs::remove_file(&self.tmp);
+		}
+	}
 }
 
 pub(super) async fn handle(cfg: Config) {
-	if let Config::Forward { secure, host, output, mut http, prefix_local } = cfg {
+	if let Config::Forward { secure, host, output, mut http, prefix_local, eager, ttl } = cfg {
 		let listen = http.listen;
 		http.rewrite = Some(http.rewrite.unwrap_or_else(|| format!("localhost:{listen}")));
 
-		http_all!(listen, get_proxy, get_root, Arc::new(ForwardConfig { secure, host, output, http, prefix_local }));
+		http_all!(listen, get_proxy, get_root, Arc::new(ForwardConfig { secure, host, output, http, prefix_local, eager, ttl, capturing: Default::default(), captures: tokio::sync::Semaphore::new(CAPTURE_JOBS), client: Client::new(), keep_unchanged: false }));
 	};
 }
 
@@ -57,6 +199,11 @@ async fn get_proxy(header: HeaderMap,
 		query,
 		method,
 	};
+	proxy(cfg, path, state, &header, payload, false).await
+}
+
+/// `force` will always fetch from upstream even resource is archived
+async fn proxy(cfg: Arc<ForwardConfig>, path: String, state: HttpState, header: &HeaderMap, payload: Body, force: bool) -> StreamResponse {
 	let npath = normalize_url_path(&cfg.output, &state, &path, cfg.prefix_local.is_none());
 	if let Some(parent) = npath.parent() {
 		if let Err(e) = create_dir_all(parent).await {
@@ -65,18 +212,31 @@ async fn get_proxy(header: HeaderMap,
 	}
 	let builder = Response::builder();
 	let method = &state.method;
-	if method == Method::GET && npath.exists() {
+	if !force && method == Method::GET && npath.exists() {
 		info!("Serving:    {path:?}");
-		serve_file(npath, builder).await
+		if cfg.is_stale(&path, &npath) && cfg.capturing.lock().unwrap().insert(npath.clone()) {
+			let state = HttpState {
+				query: state.query.clone(),
+				method: Method::GET,
+			};
+			tokio::spawn(capture(Arc::clone(&cfg), path, state, npath.clone(), true));
+		}
+		serve_file(npath, header, method, builder).await
 	} else {
 		info!("Forwarding: {path:?}");
-		let resp = Client::new()
+		let resp = cfg.client
 			.request(method.clone(), format!("{}://{}/{path}", if cfg.secure { "https" } else { "http" }, cfg.host))
 			.body(payload)
 			.query(&state.query)
 			.send()
-			.await
-			.unwrap();
+			.await;
+		let resp = match resp {
+			Ok(resp) => resp,
+			Err(e) => {
+				warn!("{e} while forwarding {path:?}");
+				return builder.status(StatusCode::BAD_GATEWAY).stream_single(e.to_string());
+			}
+		};
 		#[allow(clippy::never_loop)]
 			let (out, npath, link) = loop {
 			if npath.extension().and_then(|it| if it == "unknown_ext" { Some(()) } else { None }).is_some() {
@@ -91,9 +251,12 @@ async fn get_proxy(header: HeaderMap,
 			}
 			break (Cow::<std::path::Path>::Borrowed(&npath), Cow::<std::path::Path>::Borrowed(&npath), false);
 		};
+		// error pages never replace archived copy
+		let success = resp.status().is_success();
 		#[allow(clippy::never_loop)]
 		loop {
 			let mut builder = Response::builder()
+				.status(resp.status())
 				.header(ACCESS_CONTROL_ALLOW_ORIGIN, format!("localhost:{}", cfg.http.listen))
 				.header(ACCESS_CONTROL_ALLOW_CREDENTIALS, "true")
 				.header(ACCESS_CONTROL_ALLOW_METHODS, "*");
@@ -105,7 +268,8 @@ async fn get_proxy(header: HeaderMap,
 
 
 			if let Some(ct) = resp.headers().get(CONTENT_TYPE) {
-				let ct = ct.as_bytes();
+				// media type without parameters like `; charset=utf-8`
+				let ct = ct.as_bytes().split(|it| *it == b';').next().unwrap_or_default().trim_ascii();
 				match ct {
 					| b"text/css"
 					| b"text/javascript"
@@ -113,6 +277,9 @@ async fn get_proxy(header: HeaderMap,
 					| b"text/html"
 					| b"application/xhtml+xml"
 					=> {
+						let is_css = ct == b"text/css";
+						// scripts and json aren't scanned for subresources
+						let is_html = matches!(ct, b"text/html" | b"application/xhtml+xml");
 						let resp_len = resp.content_length().unwrap_or(512);
 						let body: Bytes = resp.bytes().await.unwrap_or_default();
 						let mut target = Vec::with_capacity(resp_len as usize);
@@ -144,13 +311,17 @@ async fn get_proxy(header: HeaderMap,
 							}
 							target.extend(x);
 						}
-						if method == Method::GET {
+						if method == Method::GET && success {
 							if let Some(parent) = npath.parent() {
 								unwrap_void!(create_dir_all(format!("{}/", parent.to_string_lossy())).await);
 							}
-							unwrap_void!(write(&npath, &target).await);
-							if link {
-								unwrap_void!(hard_link(&out, &npath));
+							let staged = Staged::new(&out, link.then_some(&npath), cfg.keep_unchanged);
+							unwrap_void!(write(&staged.tmp, &target).await);
+							staged.commit().await;
+							if cfg.eager && is_css {
+								capture_all(&cfg, &path, css_subresources(&body));
+							} else if cfg.eager && is_html {
+								capture_all(&cfg, &path, html_subresources(&body));
 							}
 						}
 
@@ -161,20 +332,27 @@ async fn get_proxy(header: HeaderMap,
 				}
 			}
 
-			let file = File::create(&out).await.unwrap();
-
-			if link {
-				unwrap_void!(hard_link(&out, &npath));
-			}
 			let inner = Box::pin(resp.bytes_stream());
-			let stream = unfold((file, inner), |(mut file, mut inner)| async move {
-				match inner.next().await? {
-					Ok(buf) => {
+			if !success {
+				break builder.stream(inner.map(|it| it.map_err(axum::Error::new)));
+			}
+			let staged = Staged::new(&out, link.then_some(&npath), cfg.keep_unchanged);
+			let file = File::create(&staged.tmp).await.unwrap();
+
+			let stream = unfold((file, inner, Some(staged)), |(mut file, mut inner, staged)| async move {
+				match inner.next().await {
+					Some(Ok(buf)) => {
 						unwrap_void!(file.write_all(&buf).await);
-						Some((Ok(buf), (file, inner)))
+						Some((Ok(buf), (file, inner, staged)))
 					}
-					Err(err) => {
-						Some((Err(axum::Error::new(err)), (file, inner)))
+					// dropped staged file is removed
+					Some(Err(err)) => Some((Err(axum::Error::new(err)), (file, inner, None))),
+					None => {
+						if let Some(staged) = staged {
+							unwrap_void!(file.shutdown().await);
+							staged.commit().await;
+						}
+						None
 					}
 				}
 			});
@@ -182,4 +360,130 @@ async fn get_proxy(header: HeaderMap,
 			break builder.stream(stream);
 		}
 	}
-}
\ No newline at end of file
+}
+
+/// Fetch every same-host reference of `page` in background so it will be archived
+/// even if browser never request it
+fn capture_all(cfg: &Arc<ForwardConfig>, page: &str, refs: Vec<String>) {
+	let Some(base) = cfg.url(page) else { return; };
+	let mut captures = vec![];
+	for r in refs {
+		let Some((path, state)) = cfg.resolve(&base, &r) else { continue; };
+		let npath = normalize_url_path(&cfg.output, &state, &path, cfg.prefix_local.is_none());
+		if npath.exists() || !cfg.capturing.lock().unwrap().insert(npath.clone()) { continue; }
+		captures.push((path, state, npath));
+	}
+	if captures.is_empty() { return; }
+	let cfg = Arc::clone(cfg);
+	tokio::spawn(futures_util::stream::iter(captures)
+		.for_each_concurrent(CAPTURE_JOBS, move |(path, state, npath)| capture(Arc::clone(&cfg), path, state, npath, false)));
+}
+
+/// Run request through proxy and drain its body, content will be written into archive as side effect
+fn capture(cfg: Arc<ForwardConfig>, path: String, state: HttpState, npath: PathBuf, force: bool) -> Pin<Box<dyn Future<Output=()> + Send>> {
+	Box::pin(async move {
+		// never closed
+		let _permit = cfg.captures.acquire().await.unwrap();
+		info!("{:<11} {path:?}", if force { "Refreshing:" } else { "Capturing:" });
+		let mut body = proxy(Arc::clone(&cfg), path, state, &HeaderMap::new(), Body::empty(), force).await.into_body();
+		while let Some(chunk) = body.data().await {
+			if let Err(e) = chunk {
+				warn!("{e} while capturing {npath:?}");
+				break;
+			}
+		}
+		cfg.capturing.lock().unwrap().remove(&npath);
+	})
+}
+
+pub(crate) struct CrawlConfig {
+	pub secure: bool,
+	pub host: String,
+	pub output: PathBuf,
+	/// host that absolute links will be rewritten into
+	pub rewrite: String,
+	pub prefix_local: Option<String>,
+	/// paths to start crawling from
+	pub seeds: Vec<String>,
+	/// only follow links to pages that start with one of these path, empty mean whole host
+	pub scope: Vec<String>,
+	/// maximum resources to fetch in single run
+	pub limit: usize,
+}
+
+/// Re-fetch every page reachable from seeds (and resources they use) into archive,
+/// return number of fetched resources
+pub(crate) async fn crawl(c: CrawlConfig) -> usize {
+	let CrawlConfig { secure, host, output, rewrite, prefix_local, seeds, scope, limit } = c;
+	let cfg = ForwardConfig::upstream(secure, host, output, rewrite, prefix_local, false);
+	let scope: Vec<&str> = scope.iter().map(|it| it.trim_start_matches('/')).collect();
+	let in_scope = |path: &str| scope.is_empty() || scope.iter().any(|it| path.starts_with(it));
+	let Some(root) = cfg.url("") else { return 0; };
+	let mut queue: Vec<_> = seeds.iter().filter_map(|it| cfg.resolve(&root, it)).collect();
+	let mut visited = HashSet::new();
+	let mut fetched = 0;
+	while !queue.is_empty() && fetched < limit {
+		let mut batch = vec![];
+		for (path, state) in queue.drain(..) {
+			if fetched + batch.len() >= limit { break; }
+			if visited.insert(normalize_url_path(&cfg.output, &state, &path, cfg.prefix_local.is_none())) {
+				batch.push((path, state));
+			}
+		}
+		fetched += batch.len();
+		let pages: Vec<_> = futures_util::stream::iter(batch)
+			.map(|(path, state)| fetch_page(Arc::clone(&cfg), path, state))
+			.buffer_unordered(8)
+			.collect()
+			.await;
+		for (path, links, subresources) in pages {
+			let Some(base) = cfg.url(&path) else { continue; };
+			queue.extend(links.iter()
+				.filter_map(|it| cfg.resolve(&base, it))
+				.filter(|(path, _)| in_scope(path)));
+			queue.extend(subresources.iter().filter_map(|it| cfg.resolve(&base, it)));
+		}
+	}
+	fetched
+}
+
+/// Fetch `path` from upstream and replace archived copy with it, archived copy is kept when upstream doesn't answer with success.
+/// Return status of upstream
+pub(crate) async fn refetch(cfg: &Arc<ForwardConfig>, path: String, state: HttpState) -> StatusCode {
+	let resp = proxy(Arc::clone(cfg), path.clone(), state, &HeaderMap::new(), Body::empty(), true).await;
+	let status = resp.status();
+	let mut body = resp.into_body();
+	while let Some(chunk) = body.data().await {
+		if let Err(e) = chunk {
+			warn!("{e} while fetching {path:?}");
+			return StatusCode::BAD_GATEWAY;
+		}
+	}
+	status
+}
+
+/// Fetch `path` from upstream into archive and return links to other pages and subresources found in it
+async fn fetch_page(cfg: Arc<ForwardConfig>, path: String, state: HttpState) -> (String, Vec<String>, Vec<String>) {
+	info!("Crawling:   {path:?}");
+	let resp = proxy(Arc::clone(&cfg), path.clone(), state, &HeaderMap::new(), Body::empty(), true).await;
+	let ct = resp.headers().get(CONTENT_TYPE).map(|it| it.as_bytes().to_vec()).unwrap_or_default();
+	let keep = ct.starts_with(b"text/html") || ct.starts_with(b"application/xhtml+xml") || ct.starts_with(b"text/css");
+	let mut body = resp.into_body();
+	let mut buf = vec![];
+	while let Some(chunk) = body.data().await {
+		match chunk {
+			Ok(chunk) => if keep { buf.extend(chunk) },
+			Err(e) => {
+				warn!("{e} while crawling {path:?}");
+				return (path, vec![], vec![]);
+			}
+		}
+	}
+	if ct.starts_with(b"text/html") || ct.starts_with(b"application/xhtml+xml") {
+		(path, html_links(&buf), html_subresources(&buf))
+	} else if ct.starts_with(b"text/css") {
+		(path, vec![], css_subresources(&buf))
+	} else {
+		(path, vec![], vec![])
+	}
+}
@@ -0,0 +1,22 @@
+use tracing::{info, warn};
+
+use crate::Config;
+use crate::search::{INDEX_FILE, SearchIndex};
+
+pub(crate) async fn run(cfg: Config) {
+	let Config::Index { path } = cfg else { unreachable!() };
+	let file = path.join(INDEX_FILE);
+	let previous = match std::fs::read(&file) {
+		Ok(content) => match serde_json::from_slice(&content) {
+			Ok(index) => Some(index),
+			Err(e) => {
+				warn!("{e}, rebuilding index from scratch");
+				None
+			}
+		},
+		Err(_) => None,
+	};
+	let (index, indexed) = tokio::task::spawn_blocking(move || SearchIndex::build(&path, previous)).await.unwrap();
+	std::fs::write(&file, serde_json::to_vec(&index).unwrap()).expect("Write search index");
+	info!("indexed {indexed} pages into {file:?}");
+}
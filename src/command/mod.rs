@@ -2,15 +2,30 @@ use crate::cli::Config;
 
 pub(crate) mod forward;
 pub(crate) mod serve;
-#[cfg(feature = "zip")]
+pub(crate) mod daemon;
+pub(crate) mod watch;
+pub(crate) mod diff;
+pub(crate) mod index;
+pub(crate) mod fill;
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
 pub(crate) mod compress;
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+pub(crate) mod extract;
+pub(crate) mod convert;
 
 pub(crate) async fn run(cfg: Config) {
 	match cfg {
 		c @ Config::Forward { .. } => forward::handle(c).await,
 		c @ Config::Serve { .. } => serve::serve_dir(c).await,
-		#[cfg(feature = "zip")]
+		c @ Config::Daemon { .. } => daemon::run(c).await,
+		c @ Config::Watch { .. } => watch::run(c).await,
+		c @ Config::Diff { .. } => diff::run(c).await,
+		c @ Config::Index { .. } => index::run(c).await,
+		c @ Config::Fill { .. } => fill::run(c).await,
+		#[cfg(any(feature = "zip", feature = "tar-archive"))]
 		c @ Config::Compress { .. } => compress::dir(c).await,
-		_ => {}
+		#[cfg(any(feature = "zip", feature = "tar-archive"))]
+		c @ Config::Extract { .. } => extract::run(c).await,
+		c @ Config::Convert { .. } => convert::run(c).await,
 	}
 }
\ No newline at end of file
@@ -1,154 +1,541 @@
 use std::collections::HashMap;
+use std::io;
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use axum::Extension;
 use axum::extract::{Path, Query, RawBody};
 use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
-use axum::http::header::CONTENT_TYPE;
+use axum::http::header::{CONTENT_TYPE, HOST};
+use axum::http::response::Builder;
 use axum::response::Response;
-#[cfg(all(feature = "serve-archive", feature = "piz"))]
-use futures_util::Stream;
-#[cfg(all(feature = "serve-archive", feature = "piz"))]
-use piz::read::FileTree;
-use tracing::info;
+use percent_encoding::{NON_ALPHANUMERIC, percent_encode};
+use reqwest::Url;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info, warn};
 
-use crate::{Config, http_all};
-use crate::common::{normalize_url_path, serve_file, StreamBodyExt, StreamResponse};
+use crate::{Config, http_all, unwrap_void};
+use crate::cli::{MatchStrategy, Mount, MountPoint};
+use crate::matcher::Matcher;
+use crate::archive::{dir_entries, Entry, list_dir, Listing};
+use crate::browse::{BROWSE_PREFIX, render_dir, render_not_found, render_results, render_search};
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+use crate::archive::{ZipEntry, ZipSource};
+#[cfg(feature = "tar-archive")]
+use crate::archive::{TarFile, TarSource};
+use crate::conditional::{BytesSource, respond, Validators};
+use crate::common::{archived_url, is_contained, normalize_url_path, serve_file, StreamBodyExt, StreamResponse, UNKNOWN_EXT};
+use crate::search::{INDEX_FILE, SearchIndex};
 use crate::state::HttpState;
+use crate::utils::rebase_links;
+
+/// Response header that tell how requested resource was matched with archived one
+static ARCHIVE_MATCH: &str = "x-archive-match";
+
+/// Separator of layers in archive path, first layer take priority
+static LAYER_SEPARATOR: char = ',';
+
+/// Layers of archive `path`, it's split only when it isn't an existing path itself
+fn split_layers(path: &str) -> Vec<&str> {
+	if std::path::Path::new(path).exists() {
+		vec![path]
+	} else {
+		path.split(LAYER_SEPARATOR).collect()
+	}
+}
 
 pub(crate) async fn serve_dir(config: Config) {
-	let Config::Serve { path, mut http } = config else { unreachable!() };
+	#[cfg(feature = "encrypt")]
+	if let Config::Serve { key_file: Some(key_file), .. } = &config {
+		crate::crypto::use_key_file(key_file.clone());
+	}
+	let Config::Serve { path, mut http, not_found, miss_log, matching, ignore_param, map, public_url, .. } = config else { unreachable!() };
+	let not_found = not_found.map(|it| std::fs::read_to_string(it).expect("Read not found template"));
 	let listen = http.listen;
 	http.rewrite = Some(http.rewrite.unwrap_or_else(|| format!("localhost:{listen}")));
+	let public_url = public_url.map(|it| Url::parse(&it).expect("Invalid public url"));
+	let public_path = public_url.as_ref().map(|it| it.path().trim_matches('/').to_string()).unwrap_or_default();
+	let open = |path: String, mount: String, name: Option<&str>| {
+		let matcher = Matcher { strategies: matching.clone(), ignore: ignore_param.clone() };
+		let base = if public_path.is_empty() { mount } else { format!("/{public_path}{mount}") };
+		let rewrite = http.rewrite.clone().unwrap();
+		let rebase = (public_url.is_some() || !base.is_empty()).then(|| Rebase {
+			hosts: vec![format!("http://{rewrite}"), format!("https://{rewrite}"), format!("//{rewrite}")],
+			base: public_url.as_ref().map(|it| it.origin().ascii_serialization()).unwrap_or_default() + &base,
+		});
+		let miss_log = miss_log.as_ref().map(|it| name.map_or_else(|| it.clone(), |name| mount_log(it, name)));
+		match ServeConfig::open(path, base, rebase, not_found.clone(), miss_log, matcher) {
+			Ok(it) => it,
+			Err(e) => {
+				error!("failed to open archive {e}");
+				std::process::exit(1);
+			}
+		}
+	};
 
-	#[cfg(all(feature = "serve-archive", feature = "piz"))]
-		let (typ, source) = if path.ends_with(".zip") {
-		(ServeType::Zip, Some(Arc::new(ZipSource::new(path.as_ref()).unwrap())))
-	} else {
-		(ServeType::Direct, None)
+	let mut sites = Sites {
+		default: path.map(|it| open(it, String::new(), None)),
+		hosts: HashMap::new(),
+		prefixes: vec![],
+		public_path: public_path.clone(),
 	};
+	for Mount { at, path } in map {
+		match at {
+			MountPoint::Host(host) => {
+				info!("serving {path:?} for host {host}");
+				let site = open(path, String::new(), Some(&host));
+				sites.hosts.insert(host, site);
+			}
+			MountPoint::Prefix(prefix) => {
+				info!("serving {path:?} at /{prefix}/");
+				let site = open(path, format!("/{prefix}"), Some(&prefix));
+				sites.prefixes.push((prefix, site));
+			}
+		}
+	}
+	// most specific prefix win
+	sites.prefixes.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+	http_all!(http.listen, serve_proxy, serve_root, Arc::new(sites));
+}
+
+/// Miss log of archive mounted at host or prefix `name`, next to `log` with name of mount before its extension.
+/// Urls are relative to archive, so each one need its own log for `fill`
+fn mount_log(log: &std::path::Path, name: &str) -> PathBuf {
+	let name: String = name.trim_matches('/').chars().map(|it| if it.is_ascii_alphanumeric() || matches!(it, '.' | '-' | '_') { it } else { '-' }).collect();
+	let stem = log.file_stem().unwrap_or_default().to_string_lossy();
+	match log.extension() {
+		Some(ext) => log.with_file_name(format!("{stem}.{name}.{}", ext.to_string_lossy())),
+		None => log.with_file_name(format!("{stem}.{name}")),
+	}
+}
+
+/// Archives served by this process, picked by `Host` header or path prefix
+struct Sites {
+	default: Option<ServeConfig>,
+	hosts: HashMap<String, ServeConfig>,
+	prefixes: Vec<(String, ServeConfig)>,
+	/// path of public url without leading and trailing slash, reverse proxy may or may not strip it
+	public_path: String,
+}
 
-	#[cfg(not(all(feature = "serve-archive", feature = "piz")))]
-		let (typ, source) = (ServeType::Direct, None);
-	let source: Option<Arc<ZipSource>> = source;
-	http_all!(http.listen, serve_proxy, serve_root, Arc::new(ServeConfig{path,typ,rewrite:http.rewrite.unwrap()}),source);
+impl Sites {
+	/// Site that serve `path` and path relative to it
+	fn route<'a>(&self, header: &HeaderMap, path: &'a str) -> Option<(&ServeConfig, &'a str)> {
+		let path = match path.strip_prefix(self.public_path.as_str()) {
+			Some(rest) if !self.public_path.is_empty() && (rest.is_empty() || rest.starts_with('/')) => rest.trim_start_matches('/'),
+			_ => path,
+		};
+		let host = header.get(HOST).and_then(|it| it.to_str().ok()).unwrap_or_default();
+		let host = host.rsplit_once(':').filter(|(_, port)| port.bytes().all(|it| it.is_ascii_digit())).map_or(host, |it| it.0);
+		if let Some(site) = self.hosts.get(&host.to_ascii_lowercase()) {
+			return Some((site, path));
+		}
+		for (prefix, site) in &self.prefixes {
+			if let Some(rest) = path.strip_prefix(prefix.as_str()) {
+				if rest.is_empty() || rest.starts_with('/') {
+					return Some((site, rest.trim_start_matches('/')));
+				}
+			}
+		}
+		self.default.as_ref().map(|it| (it, path))
+	}
 }
 
 struct ServeConfig {
+	/// archive path as given, layers separated by [`LAYER_SEPARATOR`] unless it's an existing path
 	path: String,
-	rewrite: String,
-	typ: ServeType,
+	/// url prefix this archive is mounted at, empty when mounted at root
+	base: String,
+	/// rewrite links of pages when archive isn't served at root of its rewrite host
+	rebase: Option<Rebase>,
+	/// sources to look resources up in, first one that contain resource serve it
+	layers: Vec<ServeType>,
+	search: Option<SearchIndex>,
+	/// custom template of not archived page
+	not_found: Option<String>,
+	miss_log: Option<PathBuf>,
+	matcher: Matcher,
+}
+
+impl ServeConfig {
+	fn open(path: String, base: String, rebase: Option<Rebase>, not_found: Option<String>, miss_log: Option<PathBuf>, matcher: Matcher) -> io::Result<Self> {
+		let layers = split_layers(&path)
+			.into_iter()
+			.map(|it| ServeType::open(it).map_err(|e| io::Error::new(e.kind(), format!("{it:?}: {e}"))))
+			.collect::<io::Result<Vec<_>>>()?;
+		let search = layers.iter().find_map(load_index);
+		Ok(Self { path, base, rebase, layers, search, not_found, miss_log, matcher })
+	}
+
+	/// Name of archive shown in index pages
+	fn title(&self) -> String {
+		let first = split_layers(&self.path)[0];
+		std::path::Path::new(first).file_name().map(|it| it.to_string_lossy().into_owned()).unwrap_or_default()
+	}
+
+	/// Layer that contain resource at `key` (path relative to archive root)
+	fn layer(&self, key: &str) -> Option<&ServeType> {
+		self.layers.iter().find(|it| it.contains(key))
+	}
+
+	/// Key of resource at `path` without query, split into folder and file name
+	fn base_key(&self, path: &str, state: &HttpState) -> Option<(String, String)> {
+		let base = storage_key(path, state, false)?;
+		Some(match base.rsplit_once('/') {
+			Some((dir, name)) => (dir.to_string(), name.to_string()),
+			None => (String::new(), base),
+		})
+	}
+
+	/// Key of archived variant of `path` picked by matcher, with strategy that matched
+	fn lenient(&self, path: &str, state: &HttpState) -> Option<(String, MatchStrategy)> {
+		if self.matcher.strategies.is_empty() { return None; }
+		let (dir, name) = self.base_key(path, state)?;
+		let list = self.list(&dir)?;
+		let (found, strategy) = self.matcher.find(&name, &state.query, list.iter().filter(|it| !it.is_dir).map(|it| it.name.as_str()))?;
+		let key = if dir.is_empty() { found.to_string() } else { format!("{dir}/{found}") };
+		Some((key, strategy))
+	}
+
+	/// Content of `dir` merged from every layer, `None` if no layer has it
+	fn list(&self, dir: &str) -> Option<Vec<Listing>> {
+		let mut merged: Vec<Listing> = vec![];
+		let mut found = false;
+		for layer in &self.layers {
+			let Some(list) = layer.list(dir) else { continue; };
+			found = true;
+			for it in list {
+				if !merged.iter().any(|m| m.name == it.name) {
+					merged.push(it);
+				}
+			}
+		}
+		merged.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+		found.then_some(merged)
+	}
+
+	/// Every resource of archive, resource in upper layer hide one with same path in lower layers
+	fn entries(&self) -> Vec<Entry> {
+		let mut merged: HashMap<String, Entry> = HashMap::new();
+		for layer in self.layers.iter().rev() {
+			merged.extend(layer.entries().into_iter().map(|it| (it.path.clone(), it)));
+		}
+		let mut entries: Vec<_> = merged.into_values().collect();
+		entries.sort_by(|a, b| a.path.cmp(&b.path));
+		entries
+	}
 }
 
+struct Rebase {
+	/// prefixes of absolute links to archived host
+	hosts: Vec<String>,
+	/// url or path that links should point to, without trailing slash
+	base: String,
+}
+
+impl Rebase {
+	/// Content types that may contain links
+	fn applies(&self, content_type: &str) -> bool {
+		["text/html", "text/css", "text/javascript", "application/javascript", "application/json", "application/xhtml+xml"]
+			.iter()
+			.any(|it| content_type.starts_with(it))
+	}
+}
+
+/// Path relative to archive root that resource at `path` is stored at
+/// `None` when `path` point outside of archive
+fn storage_key(path: &str, state: &HttpState, with_state: bool) -> Option<String> {
+	let key = normalize_url_path("".as_ref(), state, path, with_state).to_string_lossy().replace('\\', "/");
+	is_contained(&key).then_some(key)
+}
+
+/// Load search index created by `index` command if there is one
+fn load_index(layer: &ServeType) -> Option<SearchIndex> {
+	let content = layer.read(INDEX_FILE).ok()?;
+	match serde_json::from_slice(&content) {
+		Ok(index) => Some(index),
+		Err(e) => {
+			warn!("{e} while loading search index");
+			None
+		}
+	}
+}
+
+/// Source of archived resources, layers of a site are looked up in order
 enum ServeType {
-	Direct,
+	Direct(PathBuf),
 	#[cfg(all(feature = "serve-archive", feature = "piz"))]
-	Zip,
+	Zip(Arc<ZipSource>),
+	#[cfg(feature = "tar-archive")]
+	Tar(Arc<TarSource>),
+}
+
+impl ServeType {
+	fn open(path: &str) -> io::Result<Self> {
+		#[cfg(all(feature = "serve-archive", feature = "piz"))]
+		if path.ends_with(".zip") || path.ends_with(".zip.volumes") {
+			return ZipSource::new(path.as_ref()).map(|it| ServeType::Zip(Arc::new(it)));
+		}
+		#[cfg(feature = "tar-archive")]
+		if TarSource::is_tar(path.as_ref()) {
+			return TarSource::new(path.as_ref()).map(|it| ServeType::Tar(Arc::new(it)));
+		}
+		if !std::path::Path::new(path).is_dir() {
+			return Err(io::Error::new(io::ErrorKind::NotFound, "no such archive folder"));
+		}
+		Ok(ServeType::Direct(PathBuf::from(path)))
+	}
+
+	/// Resource at `key` (path relative to archive root) is archived in this layer
+	fn contains(&self, key: &str) -> bool {
+		match self {
+			ServeType::Direct(root) => root.join(key).is_file(),
+			#[cfg(all(feature = "serve-archive", feature = "piz"))]
+			ServeType::Zip(zip) => zip.validators(key).is_some(),
+			#[cfg(feature = "tar-archive")]
+			ServeType::Tar(tar) => tar.validators(key).is_some(),
+		}
+	}
+
+	fn list(&self, dir: &str) -> Option<Vec<Listing>> {
+		match self {
+			ServeType::Direct(root) => list_dir(root, dir),
+			#[cfg(all(feature = "serve-archive", feature = "piz"))]
+			ServeType::Zip(zip) => zip.list(dir),
+			#[cfg(feature = "tar-archive")]
+			ServeType::Tar(tar) => tar.list(dir),
+		}
+	}
+
+	fn entries(&self) -> Vec<Entry> {
+		match self {
+			ServeType::Direct(root) => dir_entries(root, root),
+			#[cfg(all(feature = "serve-archive", feature = "piz"))]
+			ServeType::Zip(zip) => zip.entries(),
+			#[cfg(feature = "tar-archive")]
+			ServeType::Tar(tar) => tar.entries(),
+		}
+	}
+
+	fn validators(&self, key: &str) -> Option<Validators> {
+		match self {
+			ServeType::Direct(root) => root.join(key).metadata().ok().filter(|it| it.is_file()).map(|it| Validators::of_file(&it)),
+			#[cfg(all(feature = "serve-archive", feature = "piz"))]
+			ServeType::Zip(zip) => zip.validators(key),
+			#[cfg(feature = "tar-archive")]
+			ServeType::Tar(tar) => tar.validators(key),
+		}
+	}
+
+	fn content_type(&self, key: &str) -> String {
+		match self {
+			ServeType::Direct(root) => {
+				let path = root.join(key);
+				let actual = std::fs::read_link(&path).unwrap_or(path);
+				mime_guess::from_path(actual).first().unwrap_or(mime_guess::mime::TEXT_HTML).to_string()
+			}
+			#[cfg(all(feature = "serve-archive", feature = "piz"))]
+			ServeType::Zip(zip) => zip.content_type(key).to_string(),
+			#[cfg(feature = "tar-archive")]
+			ServeType::Tar(tar) => tar.content_type(key).to_string(),
+		}
+	}
+
+	fn read(&self, key: &str) -> io::Result<Vec<u8>> {
+		match self {
+			ServeType::Direct(root) => std::fs::read(root.join(key)),
+			#[cfg(all(feature = "serve-archive", feature = "piz"))]
+			ServeType::Zip(zip) => zip.read(key),
+			#[cfg(feature = "tar-archive")]
+			ServeType::Tar(tar) => tar.read(key),
+		}
+	}
+
+	async fn serve(&self, key: String, header: &HeaderMap, method: &Method, builder: Builder, rebase: Option<&Rebase>) -> StreamResponse {
+		if let Some(rebase) = rebase {
+			let content_type = self.content_type(&key);
+			if rebase.applies(&content_type) {
+				if let (Some(validators), Ok(buf)) = (self.validators(&key), self.read(&key)) {
+					let body = rebase_links(&buf, &rebase.hosts, &rebase.base);
+					let validators = Validators {
+						len: body.len() as u64,
+						modified: validators.modified,
+						etag: format!("{}-r\"", validators.etag.trim_end_matches('"')),
+					};
+					return respond(builder, header, method, &validators, &content_type, BytesSource(body.into()));
+				}
+			}
+		}
+		match self {
+			ServeType::Direct(root) => {
+				serve_file(root.join(key), header, method, builder).await
+			}
+			#[cfg(all(feature = "serve-archive", feature = "piz"))]
+			ServeType::Zip(zip) => {
+				info!("serving {key:?} from zip file");
+				serve_zip(builder, key, Arc::clone(zip), header, method).await
+			}
+			#[cfg(feature = "tar-archive")]
+			ServeType::Tar(tar) => {
+				info!("serving {key:?} from tar file");
+				serve_tar(builder, key, Arc::clone(tar), header, method)
+			}
+		}
+	}
 }
 
 async fn serve_root(header: HeaderMap,
-                    extension: Extension<Arc<ServeConfig>>,
-                    source: Extension<Option<Arc<ZipSource>>>,
                     q: Query<HashMap<String, String>>,
+                    extension: Extension<Arc<Sites>>,
                     payload: RawBody) -> StreamResponse {
-	serve_proxy(header, Path(String::new()), q, extension, source, payload).await
+	serve_proxy(header, Path(String::new()), q, extension, payload).await
 }
 
 async fn serve_proxy(header: HeaderMap,
                      Path(path): Path<String>,
                      Query(query): Query<HashMap<String, String>>,
-                     Extension(cfg): Extension<Arc<ServeConfig>>,
-                     Extension(source): Extension<Option<Arc<ZipSource>>>,
+                     Extension(sites): Extension<Arc<Sites>>,
                      RawBody(_): RawBody) -> StreamResponse {
+	let Some((cfg, path)) = sites.route(&header, &path) else {
+		return Response::builder().status(StatusCode::NOT_FOUND).stream_single(String::from("no archive is served at this host or path"));
+	};
 	let method = Method::from_bytes(header.get("method").unwrap_or(&HeaderValue::from_static("GET")).as_bytes()).unwrap_or_default();
 	let state = HttpState {
 		method,
 		query,
 	};
-	let npath = normalize_url_path(cfg.path.as_ref(), &state, &path, true);
-	match cfg.typ {
-		ServeType::Direct => {
-			serve_file(npath, Response::builder()).await
+	if let Some(dir) = path.strip_prefix(BROWSE_PREFIX) {
+		if dir.trim_matches('/') == "search" {
+			return search(cfg, state.query.get("q").map_or("", String::as_str));
 		}
-		#[cfg(all(feature = "serve-archive", feature = "piz"))]
-		ServeType::Zip => {
-			info!("serving from zip file \"{}!/{path}\"", cfg.path);
-			serve_zip(cfg.path.as_ref(), npath, Arc::clone(source.as_ref().unwrap())).await
+		if dir.is_empty() || dir.starts_with('/') {
+			return browse(cfg, dir.trim_matches('/'), state.query.get("q"));
 		}
 	}
+	let Some(mut key) = storage_key(path, &state, true) else {
+		return Response::builder().status(StatusCode::BAD_REQUEST).stream_single(String::from("path point outside of archive"));
+	};
+	let mut matched = "exact";
+	if cfg.layer(&key).is_none() {
+		let Some((found, strategy)) = cfg.lenient(path, &state) else {
+			return not_found(cfg, path, &state, &key).await;
+		};
+		info!("{key:?} matched {found:?} by {}", strategy.name());
+		(key, matched) = (found, strategy.name());
+	}
+	let Some(layer) = cfg.layer(&key) else {
+		return not_found(cfg, path, &state, &key).await;
+	};
+	let builder = Response::builder().header(ARCHIVE_MATCH, matched);
+	layer.serve(key, &header, &state.method, builder, cfg.rebase.as_ref()).await
 }
 
 #[cfg(all(feature = "serve-archive", feature = "piz"))]
-#[repr(C)] // prevent field re-order
-struct ZipSource {
-	content: piz::read::DirectoryContents<'static>,
-	zip: piz::ZipArchive<'static>,
-	mmap: memmap::Mmap,
-	file: std::fs::File,
+async fn serve_zip(builder: Builder, path: String, zip: Arc<ZipSource>, header: &HeaderMap, method: &Method) -> StreamResponse {
+	if let Some(validators) = zip.validators(&path) {
+		let content_type = zip.content_type(&path).to_string();
+		respond(builder, header, method, &validators, &content_type, ZipEntry(zip, path))
+	} else {
+		builder
+			.status(StatusCode::NOT_FOUND)
+			.stream(futures_util::stream::empty())
+	}
 }
 
-#[cfg(all(feature = "serve-archive", feature = "piz"))]
-impl ZipSource {
-	fn new(path: &std::path::Path) -> std::io::Result<Self> {
-		let file = std::fs::File::open(path)?;
-		use fs4::FileExt;
-		file.lock_shared()?;
-		let mmap = unsafe { memmap::Mmap::map(&file)? };
-		let zip = piz::ZipArchive::new(&mmap).unwrap();
-		let zip: piz::ZipArchive<'static> = unsafe { std::mem::transmute(zip) };
-		let content = piz::read::as_tree(zip.entries()).unwrap();
-		let content = unsafe { std::mem::transmute(content) };
-		Ok(Self {
-			content,
-			file,
-			mmap,
-			zip,
-		})
+#[cfg(feature = "tar-archive")]
+fn serve_tar(builder: Builder, path: String, tar: Arc<TarSource>, header: &HeaderMap, method: &Method) -> StreamResponse {
+	if let Some(validators) = tar.validators(&path) {
+		let content_type = tar.content_type(&path).to_string();
+		respond(builder, header, method, &validators, &content_type, TarFile(tar, path))
+	} else {
+		builder
+			.status(StatusCode::NOT_FOUND)
+			.stream(futures_util::stream::empty())
 	}
+}
 
-	fn get(&self, path: &str) -> Option<impl Stream<Item=crate::common::StreamResponseItem> + Send + Sync + 'static> {
-		let entry = self.content.lookup(path).ok()?;
-		let content = std::sync::Mutex::new(self.zip.read(entry).ok()?);
-		let buf = [0; 4096];
-
-		Some(futures_util::stream::unfold((content, buf), |it| async {
-			tokio::task::spawn_blocking(|| {
-				Self::next_entry(it)
-			}).await.unwrap()
-		}))
+/// Index page of archived content, `query` search every resource under `dir` by path
+fn browse(cfg: &ServeConfig, dir: &str, query: Option<&String>) -> StreamResponse {
+	let title = cfg.title();
+	let builder = Response::builder().header(CONTENT_TYPE, "text/html; charset=utf-8");
+	let page = match query.filter(|it| !it.is_empty()) {
+		Some(query) => Some(render_search(&title, &cfg.base, dir, query, cfg.entries())),
+		None => cfg.list(dir).map(|it| render_dir(&title, &cfg.base, dir, it)),
+	};
+	match page {
+		Some(page) => builder.stream_single(page),
+		None => builder.status(StatusCode::NOT_FOUND).stream_single(vec![]),
 	}
+}
 
-	fn next_entry((content, mut buf): (std::sync::Mutex<Box<dyn std::io::Read + Send>>, [u8; 4096]))
-	              -> Option<(crate::common::StreamResponseItem, (std::sync::Mutex<Box<dyn std::io::Read + Send>>, [u8; 4096]))> {
-		let mut reader = content.lock().ok()?;
+/// Full-text search result page
+fn search(cfg: &ServeConfig, query: &str) -> StreamResponse {
+	let title = cfg.title();
+	let builder = Response::builder().header(CONTENT_TYPE, "text/html; charset=utf-8");
+	let Some(index) = &cfg.search else {
+		return builder.status(StatusCode::NOT_FOUND).stream_single(String::from("archive has no search index, create one with `archive-it index <archive folder>`"));
+	};
+	builder.stream_single(render_results(&title, &cfg.base, query, index.search(query, 50)))
+}
 
-		let len = match reader.read(&mut buf) {
-			Ok(n) => {
-				n
-			}
-			Err(err) => {
-				drop(reader);
-				return Some((Err(axum::Error::new(err)), (content, buf)));
-			}
-		};
-		let out = bytes::Bytes::copy_from_slice(&buf[..len]);
-		drop(reader);
-		Some((Ok(out), (content, buf)))
+/// Explain that resource isn't archived and point to similar resources, then record it into miss log
+async fn not_found(cfg: &ServeConfig, path: &str, state: &HttpState, key: &str) -> StreamResponse {
+	let mut query: Vec<_> = state.query.iter().collect();
+	query.sort();
+	let mut url = format!("/{path}");
+	for (i, (k, v)) in query.into_iter().enumerate() {
+		url.push(if i == 0 { '?' } else { '&' });
+		url.push_str(&percent_encode(k.as_bytes(), NON_ALPHANUMERIC).to_string());
+		if !v.is_empty() {
+			url.push('=');
+			url.push_str(&percent_encode(v.as_bytes(), NON_ALPHANUMERIC).to_string());
+		}
+	}
+	info!("not archived: {url}");
+	if let Some(log) = &cfg.miss_log {
+		let appended = async {
+			let mut file = OpenOptions::new().create(true).append(true).open(log).await?;
+			file.write_all(format!("{url}\n").as_bytes()).await
+		}.await;
+		unwrap_void!(appended);
 	}
+
+	// same resource with another query, or without query at all
+	let (dir, name) = cfg.base_key(path, state).unwrap_or_default();
+	let (dir, name) = (dir.as_str(), name.as_str());
+	let stem = name.strip_suffix(UNKNOWN_EXT).or_else(|| name.rsplit_once('.').map(|it| it.0)).unwrap_or(name);
+	let prefix = if dir.is_empty() { String::new() } else { format!("{dir}/") };
+	let mut neighbours: Vec<(String, String)> = cfg.list(dir)
+		.unwrap_or_default()
+		.into_iter()
+		.filter(|it| !it.is_dir && it.name.starts_with(stem))
+		.take(20)
+		.map(|it| {
+			let key = format!("{prefix}{}", it.name);
+			(cfg.base.clone() + &archived_url(&key), key)
+		})
+		.collect();
+	neighbours.push((format!("{}/{BROWSE_PREFIX}/{dir}", cfg.base), format!("{prefix} (directory)")));
+
+	let page = render_not_found(cfg.not_found.as_deref(), &url, key, &neighbours);
+	Response::builder()
+		.status(StatusCode::NOT_FOUND)
+		.header(CONTENT_TYPE, "text/html; charset=utf-8")
+		.stream_single(page)
 }
 
-#[cfg(all(feature = "serve-archive", feature = "piz"))]
-async fn serve_zip(root: &std::path::Path, npath: PathBuf, zip: Arc<ZipSource>) -> StreamResponse {
-	let path = npath.strip_prefix(root).unwrap_or(&npath).to_string_lossy();
-	let typ = mime_guess::from_path(&npath);
-	let builder = Response::builder().header(CONTENT_TYPE, typ.first().unwrap_or(mime_guess::mime::TEXT_HTML).to_string());
-	
-	if let Some(res) = zip.get(&path) {
-		builder.stream(res)
-	} else {
-		builder
-			.status(StatusCode::NOT_FOUND)
-			.stream(futures_util::stream::empty())
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn key_outside_of_archive() {
+		let state = HttpState { query: HashMap::new(), method: Method::GET };
+		assert_eq!(storage_key("docs/page.html", &state, true).as_deref(), Some("docs/page.html"));
+		assert_eq!(storage_key("", &state, true).as_deref(), Some("index.GET.unknown_ext"));
+		assert_eq!(storage_key("../../../etc/resolv.conf", &state, true), None);
+		assert_eq!(storage_key("docs/../../etc/", &state, false), None);
+		assert_eq!(storage_key("/etc/resolv.conf", &state, true), None);
 	}
-}
\ No newline at end of file
+}
@@ -0,0 +1,229 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+use tokio::fs::{copy, create_dir_all, read};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::{Config, unwrap_void};
+use crate::command::forward::{ForwardConfig, refetch};
+use crate::links::text_content;
+
+/// Folder inside archive that keep every capture of watched page that changed, as `<timestamp>/<path>`
+pub(crate) static SNAPSHOT_DIR: &str = ".snapshots";
+
+/// Timestamp format of snapshot folder
+pub(crate) static SNAPSHOT_FORMAT: &str = "%Y%m%d%H%M%S";
+
+#[derive(Serialize)]
+struct Change<'a> {
+	url: &'a str,
+	path: &'a Path,
+	snapshot: &'a Path,
+	added: usize,
+	removed: usize,
+	diff: String,
+}
+
+pub(crate) async fn run(cfg: Config) {
+	let Config::Watch { secure, host, output, paths, interval, text, rewrite, prefix_local, exec, webhook, once } = cfg else { unreachable!() };
+	// unchanged page keep time it was captured, previous snapshot is named after it
+	let upstream = ForwardConfig::upstream(secure, host, output.clone(), rewrite, prefix_local, true);
+	loop {
+		for url in &paths {
+			let Some((path, state, npath)) = upstream.archived_path(url) else {
+				warn!("{url:?} is not a path of upstream");
+				continue;
+			};
+			let old = read(&npath).await.ok();
+			let old_time = npath.metadata().and_then(|it| it.modified()).ok();
+			let status = refetch(&upstream, path, state).await;
+			if !status.is_success() {
+				warn!("{url:?} responded with {status}");
+				continue;
+			}
+			let Ok(new) = read(&npath).await else { continue; };
+			let Some(old) = old else {
+				info!("{url:?} archived for the first time");
+				continue;
+			};
+			let (old_cmp, new_cmp) = if text {
+				(text_content(&old), text_content(&new))
+			} else {
+				(String::from_utf8_lossy(&old).into_owned(), String::from_utf8_lossy(&new).into_owned())
+			};
+			if old_cmp == new_cmp {
+				info!("{url:?} unchanged");
+				continue;
+			}
+
+			let rel = npath.strip_prefix(&output).unwrap_or(&npath);
+			if let Some(old_time) = old_time {
+				// keep version that was archived before first detected change
+				let previous = snapshot_path(&output, DateTime::<Utc>::from(old_time), rel);
+				if !previous.exists() {
+					unwrap_void!(save_snapshot(&npath, &previous, Some(&old)).await);
+				}
+			}
+			let snapshot = snapshot_path(&output, Utc::now(), rel);
+			unwrap_void!(save_snapshot(&npath, &snapshot, None).await);
+
+			let diff = TextDiff::from_lines(&old_cmp, &new_cmp);
+			let (mut added, mut removed) = (0, 0);
+			for change in diff.iter_all_changes() {
+				match change.tag() {
+					ChangeTag::Insert => added += 1,
+					ChangeTag::Delete => removed += 1,
+					ChangeTag::Equal => {}
+				}
+			}
+			let change = Change {
+				url,
+				path: rel,
+				snapshot: &snapshot,
+				added,
+				removed,
+				diff: diff.unified_diff().context_radius(2).header("archived", "upstream").to_string(),
+			};
+			info!("{url:?} changed +{added} -{removed}, saved into {snapshot:?}");
+			if let Some(exec) = &exec {
+				unwrap_void!(notify_exec(exec, &change).await);
+			}
+			if let Some(webhook) = &webhook {
+				unwrap_void!(notify_webhook(webhook, &change).await);
+			}
+		}
+		if once { break; }
+		sleep(interval).await;
+	}
+}
+
+fn snapshot_path(output: &Path, time: DateTime<Utc>, rel: &Path) -> PathBuf {
+	output.join(SNAPSHOT_DIR).join(time.format(SNAPSHOT_FORMAT).to_string()).join(rel)
+}
+
+/// Copy archived file into snapshot, or write `content` if archived file already replaced
+async fn save_snapshot(npath: &Path, snapshot: &Path, content: Option<&[u8]>) -> std::io::Result<()> {
+	if let Some(parent) = snapshot.parent() {
+		create_dir_all(parent).await?;
+	}
+	match content {
+		Some(content) => tokio::fs::write(snapshot, content).await,
+		None => copy(npath, snapshot).await.map(|_| ()),
+	}
+}
+
+async fn notify_exec(exec: &str, change: &Change<'_>) -> std::io::Result<()> {
+	let mut child = Command::new("sh")
+		.arg("-c")
+		.arg(exec)
+		.env("ARCHIVE_URL", change.url)
+		.env("ARCHIVE_PATH", change.path)
+		.env("ARCHIVE_SNAPSHOT", change.snapshot)
+		.env("ARCHIVE_SUMMARY", format!("+{} -{}", change.added, change.removed))
+		.stdin(Stdio::piped())
+		.spawn()?;
+	if let Some(mut stdin) = child.stdin.take() {
+		stdin.write_all(change.diff.as_bytes()).await?;
+	}
+	let status = child.wait().await?;
+	if !status.success() {
+		warn!("{exec:?} exited with {status}");
+	}
+	Ok(())
+}
+
+async fn notify_webhook(url: &str, change: &Change<'_>) -> anyhow::Result<()> {
+	let resp = reqwest::Client::new()
+		.post(url)
+		.header(reqwest::header::CONTENT_TYPE, "application/json")
+		.body(serde_json::to_vec(change)?)
+		.send()
+		.await?;
+	if !resp.status().is_success() {
+		warn!("webhook {url:?} responded with {}", resp.status());
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{Arc, Mutex};
+	use std::time::Duration;
+
+	use axum::extract::State;
+	use axum::routing::{get, post};
+	use axum::Router;
+
+	use super::*;
+
+	#[derive(Clone, Default)]
+	struct Upstream {
+		page: Arc<Mutex<String>>,
+		hooks: Arc<Mutex<Vec<serde_json::Value>>>,
+	}
+
+	/// Plain http upstream that also receive webhook, on random port
+	fn listen(upstream: Upstream) -> String {
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let host = listener.local_addr().unwrap().to_string();
+		let app = Router::new()
+			.route("/page.html", get(|State(it): State<Upstream>| async move { it.page.lock().unwrap().clone() }))
+			.route("/hook", post(|State(it): State<Upstream>, body: String| async move {
+				it.hooks.lock().unwrap().push(serde_json::from_str(&body).unwrap());
+			}))
+			.with_state(upstream);
+		tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+		host
+	}
+
+	#[tokio::test]
+	async fn change_is_notified() {
+		let upstream = Upstream::default();
+		let host = listen(upstream.clone());
+		let output = std::env::temp_dir().join(format!("archive-it-watch-{}", std::process::id()));
+		let check = || run(Config::Watch {
+			secure: false,
+			host: host.clone(),
+			output: output.clone(),
+			paths: vec!["/page.html".to_string()],
+			interval: Duration::ZERO,
+			text: false,
+			rewrite: "localhost:3000".to_string(),
+			prefix_local: None,
+			exec: None,
+			webhook: Some(format!("http://{host}/hook")),
+			once: true,
+		});
+		let archived = output.join("page.html");
+		*upstream.page.lock().unwrap() = "first\n".to_string();
+		check().await;
+		assert_eq!(std::fs::read_to_string(&archived).unwrap(), "first\n");
+		// snapshots are named by second, pretend capture happened earlier
+		let captured = std::time::SystemTime::now() - Duration::from_secs(3600);
+		std::fs::File::options().write(true).open(&archived).unwrap().set_modified(captured).unwrap();
+
+		// same content leave archived copy untouched
+		check().await;
+		assert_eq!(archived.metadata().unwrap().modified().unwrap(), captured);
+		assert!(upstream.hooks.lock().unwrap().is_empty());
+
+		*upstream.page.lock().unwrap() = "second\n".to_string();
+		check().await;
+		assert_eq!(std::fs::read_to_string(&archived).unwrap(), "second\n");
+		let hooks = upstream.hooks.lock().unwrap().clone();
+		assert_eq!(hooks.len(), 1);
+		assert_eq!(hooks[0]["url"], "/page.html");
+		assert_eq!((hooks[0]["added"].as_u64(), hooks[0]["removed"].as_u64()), (Some(1), Some(1)));
+		assert!(hooks[0]["diff"].as_str().unwrap().contains("-first\n+second\n"));
+		// version archived before the change is kept under time it was captured
+		let previous = snapshot_path(&output, DateTime::<Utc>::from(captured), Path::new("page.html"));
+		assert_eq!(std::fs::read_to_string(previous).unwrap(), "first\n");
+		std::fs::remove_dir_all(&output).unwrap();
+	}
+}
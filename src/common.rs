@@ -1,17 +1,15 @@
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::pin::Pin;
 
 use axum::body::{Bytes, StreamBody};
-use axum::http::StatusCode;
-use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, Method, StatusCode};
 use axum::http::response::Builder;
 use axum::response::Response;
-use bytes::BytesMut;
 use futures_util::Stream;
-use futures_util::stream::unfold;
 use percent_encoding::{NON_ALPHANUMERIC, percent_encode};
-use tokio::fs::{File, read_link};
-use tokio::io::AsyncReadExt;
+use tokio::fs::{metadata, read_link};
+
+use crate::conditional::{FileSource, respond, Validators};
 
 use crate::state::HttpState;
 use crate::stream_single;
@@ -36,7 +34,21 @@ impl StreamBodyExt for Builder {
 	}
 }
 
-static UNKNOWN_EXT: &str = "unknown_ext";
+pub(crate) static UNKNOWN_EXT: &str = "unknown_ext";
+
+/// `path` relative to archive root stay inside of it, it has no `..`, root or prefix component
+pub(crate) fn is_contained(path: &str) -> bool {
+	Path::new(path).components().all(|it| matches!(it, Component::Normal(_) | Component::CurDir))
+}
+
+/// Whether `name` is the resource that forward stored as `stem` followed by `unknown_ext`, saved again with real extension
+/// (`page.html` for `pageunknown_ext`, `index.GET.html` for root `index.GET.unknown_ext`). Both are in same folder
+pub(crate) fn is_typed_sibling(stem: &str, name: &str) -> bool {
+	let Some(rest) = name.strip_prefix(stem).filter(|_| !name.ends_with(UNKNOWN_EXT)) else { return false };
+	// stem of root page end with dot already
+	let ext = if stem.ends_with('.') { Some(rest) } else { rest.strip_prefix('.') };
+	ext.is_some_and(|it| !it.is_empty() && !it.contains('/'))
+}
 
 pub(crate) fn normalize_url_path(output: &Path, state: &HttpState, path: &str, with_state: bool) -> PathBuf {
 	if path.is_empty() {
@@ -79,33 +91,52 @@ pub(crate) fn normalize_url_path(output: &Path, state: &HttpState, path: &str, w
 	}
 }
 
-pub(crate) async fn serve_file(npath: PathBuf, mut builder: Builder) -> StreamResponse {
+/// Best effort reverse of [`normalize_url_path`], url that will be resolved into archived `path`
+pub(crate) fn archived_url(path: &str) -> String {
+	match path.strip_suffix(UNKNOWN_EXT) {
+		Some(stem) if stem.starts_with("index.") && !stem.contains('/') => String::from("/"),
+		Some(stem) => format!("/{stem}"),
+		None => format!("/{path}"),
+	}
+}
+
+pub(crate) async fn serve_file(npath: PathBuf, headers: &HeaderMap, method: &Method, builder: Builder) -> StreamResponse {
 	let actual = read_link(&npath).await.unwrap_or(npath);
 	let typ = mime_guess::from_path(&actual);
-	let Ok(mut file) = File::open(&actual).await else {
+	let Ok(meta) = metadata(&actual).await else {
 		return builder.status(StatusCode::NOT_FOUND).stream_single(vec![]);
 	};
-	let meta = file.metadata().await.unwrap();
-	builder = builder.header(CONTENT_TYPE, typ.first().unwrap_or(mime_guess::mime::TEXT_HTML).to_string());
-
-	if meta.len() > (1 << 18) {
-		builder.stream(unfold((file, BytesMut::with_capacity(4096)), |(mut file, mut buf)| async move {
-			buf.reserve(4096);
-			match file.read_buf(&mut buf).await {
-				Ok(0) => {
-					None
-				}
-				Ok(n) => {
-					Some((Ok(buf.split_to(n).freeze()), (file, buf)))
-				}
-				Err(err) => {
-					Some((Err(axum::Error::new(err)), (file, buf)))
-				}
-			}
-		}))
-	} else {
-		let mut buf = Vec::with_capacity(meta.len() as usize);
-		file.read_to_end(&mut buf).await.unwrap();
-		builder.stream_single(buf)
+	if !meta.is_file() {
+		return builder.status(StatusCode::NOT_FOUND).stream_single(vec![]);
+	}
+	let content_type = typ.first().unwrap_or(mime_guess::mime::TEXT_HTML).to_string();
+	respond(builder, headers, method, &Validators::of_file(&meta), &content_type, FileSource(actual))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{is_contained, is_typed_sibling};
+
+	#[test]
+	fn contained() {
+		assert!(is_contained(""));
+		assert!(is_contained("docs/page.html"));
+		assert!(is_contained("./docs"));
+		assert!(!is_contained("../etc"));
+		assert!(!is_contained("docs/../../etc"));
+		assert!(!is_contained("/etc/resolv.conf"));
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn typed_sibling() {
+		assert!(is_typed_sibling("index.GET.", "index.GET.html"));
+		assert!(is_typed_sibling("page", "page.html"));
+		assert!(is_typed_sibling("docs/page", "docs/page.html"));
+		assert!(!is_typed_sibling("index.GET.", "index.GET.unknown_ext"));
+		assert!(!is_typed_sibling("index.GET.", "index.GET."));
+		assert!(!is_typed_sibling("page", "pages.html"));
+		assert!(!is_typed_sibling("page", "page-q=1.html"));
+		assert!(!is_typed_sibling("page", "page."));
+		assert!(!is_typed_sibling("docs/page", "docs/page/inner.html"));
+	}
+}
@@ -0,0 +1,413 @@
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::body::Bytes;
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use axum::http::header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, IF_UNMODIFIED_SINCE, LAST_MODIFIED, RANGE};
+use axum::http::response::Builder;
+use bytes::BytesMut;
+use futures_util::stream::{iter, unfold};
+use futures_util::StreamExt;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+
+use crate::{box_stream, stream_single};
+use crate::common::{StreamBodyExt, StreamResponse, StreamResponseType};
+
+/// Validators of a resource, used to answer conditional and range requests (RFC 9110)
+pub(crate) struct Validators {
+	pub len: u64,
+	pub modified: Option<SystemTime>,
+	/// strong entity tag including quotes
+	pub etag: String,
+}
+
+impl Validators {
+	/// Validators of archived file, etag is derived from size and modification time
+	pub(crate) fn of_file(meta: &std::fs::Metadata) -> Self {
+		let modified = meta.modified().ok();
+		let nanos = modified.and_then(|it| it.duration_since(UNIX_EPOCH).ok()).unwrap_or_default().as_nanos();
+		Self {
+			len: meta.len(),
+			modified,
+			etag: format!("\"{:x}-{nanos:x}\"", meta.len()),
+		}
+	}
+}
+
+/// Content that can be read from arbitrary offset
+pub(crate) trait RangeSource: Send + Sync + 'static {
+	/// Stream `len` bytes starting at `start`
+	fn range(&self, start: u64, len: u64) -> StreamResponseType;
+}
+
+pub(crate) struct FileSource(pub PathBuf);
+
+impl RangeSource for FileSource {
+	fn range(&self, start: u64, len: u64) -> StreamResponseType {
+		let path = self.0.clone();
+		box_stream!(unfold((None, path, len), move |(file, path, remaining)| async move {
+			if remaining == 0 { return None; }
+			let mut file = match file {
+				Some(file) => file,
+				None => {
+					let opened = async {
+						let mut file = File::open(&path).await?;
+						file.seek(SeekFrom::Start(start)).await?;
+						Ok::<_, std::io::Error>(file)
+					}.await;
+					match opened {
+						Ok(file) => file,
+						Err(err) => return Some((Err(axum::Error::new(err)), (None, path, 0))),
+					}
+				}
+			};
+			let mut buf = BytesMut::with_capacity(remaining.min(1 << 16) as usize);
+			match (&mut file).take(remaining).read_buf(&mut buf).await {
+				Ok(0) => None,
+				Ok(n) => Some((Ok(buf.freeze()), (Some(file), path, remaining - n as u64))),
+				Err(err) => Some((Err(axum::Error::new(err)), (None, path, 0))),
+			}
+		}))
+	}
+}
+
+/// Content already in memory, eg. archived page with rewritten links
+pub(crate) struct BytesSource(pub Bytes);
+
+impl RangeSource for BytesSource {
+	fn range(&self, start: u64, len: u64) -> StreamResponseType {
+		stream_single!(self.0.slice(start as usize..(start + len) as usize))
+	}
+}
+
+/// Build response of resource described by `validators`, honor preconditions
+/// (`If-Match`, `If-None-Match`, `If-Modified-Since`, `If-Unmodified-Since`) and
+/// single or multiple byte ranges (`Range`, `If-Range`)
+pub(crate) fn respond(mut builder: Builder, headers: &HeaderMap, method: &Method, validators: &Validators, content_type: &str, source: impl RangeSource) -> StreamResponse {
+	let len = validators.len;
+	builder = builder
+		.header(ETAG, &validators.etag)
+		.header(ACCEPT_RANGES, "bytes");
+	if let Some(modified) = validators.modified {
+		builder = builder.header(LAST_MODIFIED, httpdate::fmt_http_date(modified));
+	}
+	let safe = method == Method::GET || method == Method::HEAD;
+
+	// RFC 9110 section 13.2.2
+	if let Some(tags) = headers.get(IF_MATCH) {
+		if !etag_match(tags, &validators.etag, false) {
+			return builder.status(StatusCode::PRECONDITION_FAILED).stream_single(vec![]);
+		}
+	} else if let Some(since) = header_date(headers, IF_UNMODIFIED_SINCE) {
+		if validators.modified.is_some_and(|it| truncate(it) > since) {
+			return builder.status(StatusCode::PRECONDITION_FAILED).stream_single(vec![]);
+		}
+	}
+	if let Some(tags) = headers.get(IF_NONE_MATCH) {
+		if etag_match(tags, &validators.etag, true) {
+			let status = if safe { StatusCode::NOT_MODIFIED } else { StatusCode::PRECONDITION_FAILED };
+			return builder.status(status).stream_single(vec![]);
+		}
+	} else if let Some(since) = header_date(headers, IF_MODIFIED_SINCE) {
+		if safe && validators.modified.is_some_and(|it| truncate(it) <= since) {
+			return builder.status(StatusCode::NOT_MODIFIED).stream_single(vec![]);
+		}
+	}
+
+	let ranges = if method == Method::GET && if_range(headers, validators) {
+		headers.get(RANGE).and_then(|it| parse_range(it.to_str().ok()?, len))
+	} else {
+		None
+	};
+	match ranges {
+		None => {
+			builder
+				.header(CONTENT_TYPE, content_type)
+				.header(CONTENT_LENGTH, len)
+				.stream(source.range(0, len))
+		}
+		Some(ranges) if ranges.is_empty() => {
+			builder
+				.status(StatusCode::RANGE_NOT_SATISFIABLE)
+				.header(CONTENT_TYPE, content_type)
+				.header(CONTENT_RANGE, format!("bytes */{len}"))
+				.stream_single(vec![])
+		}
+		Some(ranges) if ranges.len() == 1 => {
+			let range = &ranges[0];
+			builder
+				.status(StatusCode::PARTIAL_CONTENT)
+				.header(CONTENT_TYPE, content_type)
+				.header(CONTENT_RANGE, format!("bytes {}-{}/{len}", range.start(), range.end()))
+				.header(CONTENT_LENGTH, range.end() - range.start() + 1)
+				.stream(source.range(*range.start(), range.end() - range.start() + 1))
+		}
+		Some(ranges) => {
+			let boundary: String = rand::thread_rng()
+				.sample_iter(Alphanumeric)
+				.take(24)
+				.map(char::from)
+				.collect();
+			let mut parts = vec![];
+			let mut total = 0;
+			for range in ranges {
+				let head = format!("\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes {}-{}/{len}\r\n\r\n", range.start(), range.end());
+				let part_len = range.end() - range.start() + 1;
+				total += head.len() as u64 + part_len;
+				parts.push(stream_single!(Bytes::from(head)));
+				parts.push(source.range(*range.start(), part_len));
+			}
+			let tail = format!("\r\n--{boundary}--\r\n");
+			total += tail.len() as u64;
+			parts.push(stream_single!(Bytes::from(tail)));
+			builder
+				.status(StatusCode::PARTIAL_CONTENT)
+				.header(CONTENT_TYPE, format!("multipart/byteranges; boundary={boundary}"))
+				.header(CONTENT_LENGTH, total)
+				.stream(iter(parts).flatten())
+		}
+	}
+}
+
+/// `If-Range` is absent or still match current representation
+fn if_range(headers: &HeaderMap, validators: &Validators) -> bool {
+	let Some(value) = headers.get(IF_RANGE) else { return true; };
+	let Ok(value) = value.to_str() else { return false; };
+	if value.starts_with('"') || value.starts_with("W/") {
+		// weak tag never match in If-Range
+		value == validators.etag
+	} else {
+		// only exact date is acceptable
+		httpdate::parse_http_date(value).ok().zip(validators.modified).is_some_and(|(date, modified)| date == truncate(modified))
+	}
+}
+
+/// Parse `bytes=` range header into satisfiable ranges, `None` if header is invalid (should be ignored)
+/// and empty if no range can be satisfied
+fn parse_range(value: &str, len: u64) -> Option<Vec<RangeInclusive<u64>>> {
+	let specs = value.trim().strip_prefix("bytes=")?;
+	let mut ranges = vec![];
+	for spec in specs.split(',') {
+		let (start, end) = spec.trim().split_once('-')?;
+		let range = match (start.trim(), end.trim()) {
+			("", "") => return None,
+			("", suffix) => {
+				let suffix: u64 = suffix.parse().ok()?;
+				if suffix == 0 || len == 0 { continue; }
+				len.saturating_sub(suffix)..=len - 1
+			}
+			(start, end) => {
+				let start: u64 = start.parse().ok()?;
+				let end = if end.is_empty() { u64::MAX } else { end.parse().ok()? };
+				if end < start { return None; }
+				if start >= len { continue; }
+				start..=end.min(len - 1)
+			}
+		};
+		ranges.push(range);
+	}
+	ranges.sort_by_key(|it| *it.start());
+	// coalesce overlapping ranges so client can't request same bytes over and over
+	let mut merged: Vec<RangeInclusive<u64>> = vec![];
+	for range in ranges {
+		match merged.last_mut() {
+			Some(last) if *range.start() <= last.end().saturating_add(1) => {
+				*last = *last.start()..=*range.end().max(last.end());
+			}
+			_ => merged.push(range),
+		}
+	}
+	Some(merged)
+}
+
+fn etag_match(header: &HeaderValue, etag: &str, weak: bool) -> bool {
+	let Ok(header) = header.to_str() else { return false; };
+	if header.trim() == "*" { return true; }
+	header.split(',').map(str::trim).any(|tag| {
+		if weak {
+			tag.trim_start_matches("W/") == etag
+		} else {
+			tag == etag
+		}
+	})
+}
+
+fn header_date(headers: &HeaderMap, name: axum::http::header::HeaderName) -> Option<SystemTime> {
+	httpdate::parse_http_date(headers.get(name)?.to_str().ok()?).ok()
+}
+
+/// Http date only have second precision
+fn truncate(time: SystemTime) -> SystemTime {
+	let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+	UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+#[cfg(test)]
+mod tests {
+	use axum::body::HttpBody;
+	use axum::http::header::HeaderName;
+	use axum::http::Response;
+
+	use super::*;
+
+	const CONTENT: &[u8] = b"0123456789";
+
+	/// `Last-Modified` of served content
+	const MODIFIED: &str = "Sun, 09 Sep 2001 01:46:40 GMT";
+
+	async fn get(headers: &[(HeaderName, &str)]) -> (StatusCode, HeaderMap, Vec<u8>) {
+		request(Method::GET, headers).await
+	}
+
+	async fn request(method: Method, headers: &[(HeaderName, &str)]) -> (StatusCode, HeaderMap, Vec<u8>) {
+		let headers: HeaderMap = headers.iter().map(|(k, v)| (k.clone(), HeaderValue::from_str(v).unwrap())).collect();
+		let validators = Validators {
+			len: CONTENT.len() as u64,
+			modified: Some(UNIX_EPOCH + Duration::from_millis(1_000_000_000_250)),
+			etag: String::from("\"abc\""),
+		};
+		let res = respond(Response::builder(), &headers, &method, &validators, "text/plain", BytesSource(Bytes::from_static(CONTENT)));
+		let (parts, mut body) = res.into_parts();
+		let mut content = vec![];
+		while let Some(chunk) = body.data().await {
+			content.extend(chunk.unwrap());
+		}
+		(parts.status, parts.headers, content)
+	}
+
+	#[test]
+	fn ranges() {
+		assert_eq!(parse_range("bytes=-3", 10), Some(vec![7..=9]));
+		assert_eq!(parse_range("bytes=-20", 10), Some(vec![0..=9]));
+		assert_eq!(parse_range("bytes=4-", 10), Some(vec![4..=9]));
+		assert_eq!(parse_range("bytes=8-20", 10), Some(vec![8..=9]));
+		assert_eq!(parse_range("bytes=6-7, 0-1", 10), Some(vec![0..=1, 6..=7]));
+		// overlapping and adjacent ranges are served once
+		assert_eq!(parse_range("bytes=0-4,3-6,7-7", 10), Some(vec![0..=7]));
+		assert_eq!(parse_range("bytes=10-", 10), Some(vec![]));
+		assert_eq!(parse_range("bytes=-0", 10), Some(vec![]));
+		assert_eq!(parse_range("bytes=-3", 0), Some(vec![]));
+		assert_eq!(parse_range("bytes=5-3", 10), None);
+		assert_eq!(parse_range("bytes=-", 10), None);
+		assert_eq!(parse_range("items=0-1", 10), None);
+	}
+
+	#[tokio::test]
+	async fn suffix_range() {
+		let (status, headers, body) = get(&[(RANGE, "bytes=-3")]).await;
+		assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+		assert_eq!(headers[CONTENT_RANGE], "bytes 7-9/10");
+		assert_eq!(body, b"789");
+	}
+
+	#[tokio::test]
+	async fn open_ended_range() {
+		let (status, headers, body) = get(&[(RANGE, "bytes=6-")]).await;
+		assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+		assert_eq!(headers[CONTENT_RANGE], "bytes 6-9/10");
+		assert_eq!(headers[CONTENT_LENGTH], "4");
+		assert_eq!(body, b"6789");
+	}
+
+	#[tokio::test]
+	async fn unsatisfiable_range() {
+		let (status, headers, body) = get(&[(RANGE, "bytes=10-12")]).await;
+		assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+		assert_eq!(headers[CONTENT_RANGE], "bytes */10");
+		assert!(body.is_empty());
+	}
+
+	#[tokio::test]
+	async fn multiple_ranges() {
+		let (status, headers, body) = get(&[(RANGE, "bytes=0-1,5-6")]).await;
+		assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+		assert_eq!(headers.get_all(CONTENT_TYPE).iter().count(), 1);
+		let content_type = headers[CONTENT_TYPE].to_str().unwrap();
+		let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+		let expected = format!(
+			"\r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+			\r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 5-6/10\r\n\r\n56\
+			\r\n--{boundary}--\r\n"
+		);
+		assert_eq!(String::from_utf8(body).unwrap(), expected);
+		assert_eq!(headers[CONTENT_LENGTH], expected.len().to_string().as_str());
+	}
+
+	#[tokio::test]
+	async fn if_range_mismatch() {
+		// stale validator get whole representation instead of part of new one
+		let (status, headers, body) = get(&[(RANGE, "bytes=0-1"), (IF_RANGE, "\"old\"")]).await;
+		assert_eq!(status, StatusCode::OK);
+		assert!(!headers.contains_key(CONTENT_RANGE));
+		assert_eq!(body, CONTENT);
+		let (status, _, body) = get(&[(RANGE, "bytes=0-1"), (IF_RANGE, "\"abc\"")]).await;
+		assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+		assert_eq!(body, b"01");
+	}
+
+	#[tokio::test]
+	async fn if_range_date() {
+		let (status, _, body) = get(&[(RANGE, "bytes=0-1"), (IF_RANGE, "Sun, 09 Sep 2001 01:46:39 GMT")]).await;
+		assert_eq!(status, StatusCode::OK);
+		assert_eq!(body, CONTENT);
+		let (status, _, body) = get(&[(RANGE, "bytes=0-1"), (IF_RANGE, MODIFIED)]).await;
+		assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+		assert_eq!(body, b"01");
+	}
+
+	#[tokio::test]
+	async fn if_none_match() {
+		let (status, headers, body) = get(&[(IF_NONE_MATCH, "\"old\", W/\"abc\"")]).await;
+		assert_eq!(status, StatusCode::NOT_MODIFIED);
+		assert_eq!(headers[ETAG], "\"abc\"");
+		assert!(body.is_empty());
+		let (status, _, body) = get(&[(IF_NONE_MATCH, "\"old\"")]).await;
+		assert_eq!(status, StatusCode::OK);
+		assert_eq!(body, CONTENT);
+		// unsafe method can't be answered from cache
+		let (status, _, _) = request(Method::POST, &[(IF_NONE_MATCH, "*")]).await;
+		assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+	}
+
+	#[tokio::test]
+	async fn if_modified_since() {
+		let (status, headers, body) = get(&[(IF_MODIFIED_SINCE, MODIFIED)]).await;
+		assert_eq!(status, StatusCode::NOT_MODIFIED);
+		assert_eq!(headers[LAST_MODIFIED], MODIFIED);
+		assert!(body.is_empty());
+		let (status, _, body) = get(&[(IF_MODIFIED_SINCE, "Sun, 09 Sep 2001 01:46:39 GMT")]).await;
+		assert_eq!(status, StatusCode::OK);
+		assert_eq!(body, CONTENT);
+		// If-None-Match takes precedence
+		let (status, _, _) = get(&[(IF_NONE_MATCH, "\"old\""), (IF_MODIFIED_SINCE, MODIFIED)]).await;
+		assert_eq!(status, StatusCode::OK);
+	}
+
+	#[tokio::test]
+	async fn if_match() {
+		let (status, _, body) = get(&[(IF_MATCH, "\"old\"")]).await;
+		assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+		assert!(body.is_empty());
+		// weak comparison is not allowed
+		let (status, _, _) = get(&[(IF_MATCH, "W/\"abc\"")]).await;
+		assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+		let (status, _, body) = get(&[(IF_MATCH, "\"old\", \"abc\"")]).await;
+		assert_eq!(status, StatusCode::OK);
+		assert_eq!(body, CONTENT);
+	}
+
+	#[tokio::test]
+	async fn if_unmodified_since() {
+		let (status, _, body) = get(&[(IF_UNMODIFIED_SINCE, "Sun, 09 Sep 2001 01:46:39 GMT")]).await;
+		assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+		assert!(body.is_empty());
+		let (status, _, body) = get(&[(IF_UNMODIFIED_SINCE, MODIFIED)]).await;
+		assert_eq!(status, StatusCode::OK);
+		assert_eq!(body, CONTENT);
+	}
+}
@@ -0,0 +1,272 @@
+//! Passphrase of encrypted archives, AES encryption of zip entries and age encryption of tar
+// nothing is encrypted without an archive format
+#![cfg_attr(not(any(feature = "zip", feature = "tar-archive", feature = "serve-archive")), allow(dead_code))]
+
+use std::io;
+#[cfg(any(feature = "zip", feature = "tar-archive", all(feature = "serve-archive", feature = "piz")))]
+use std::io::Read;
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+#[cfg(any(feature = "zip", all(feature = "serve-archive", feature = "piz")))]
+use hmac::{Hmac, Mac};
+#[cfg(any(feature = "zip", all(feature = "serve-archive", feature = "piz")))]
+use sha1::Sha1;
+
+/// Environment variable that hold passphrase, used when no key file is given
+pub(crate) const KEY_ENV: &str = "ARCHIVE_IT_KEY";
+/// Length of salt of AES-256 entries
+#[cfg(any(feature = "zip", all(feature = "serve-archive", feature = "piz")))]
+const AES_SALT: usize = 16;
+/// Length of truncated hmac that end AES entries
+#[cfg(any(feature = "zip", all(feature = "serve-archive", feature = "piz")))]
+pub(crate) const AES_MAC: usize = 10;
+/// Key derivation rounds of WinZip AES, fixed by the format
+#[cfg(any(feature = "zip", all(feature = "serve-archive", feature = "piz")))]
+const AES_ROUNDS: u32 = 1000;
+
+static KEY_FILE: OnceLock<PathBuf> = OnceLock::new();
+static PASSPHRASE: OnceLock<String> = OnceLock::new();
+
+/// Read passphrase from first line of `path` instead of environment or prompt
+pub(crate) fn use_key_file(path: PathBuf) {
+	let _ = KEY_FILE.set(path);
+}
+
+/// Passphrase from key file, `ARCHIVE_IT_KEY` or prompt, resolved once per process.
+/// `confirm` ask twice when prompting, for new archives
+pub(crate) fn passphrase(confirm: bool) -> io::Result<&'static str> {
+	if let Some(it) = PASSPHRASE.get() {
+		return Ok(it);
+	}
+	let key = if let Some(path) = KEY_FILE.get() {
+		let content = std::fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{path:?}: {e}")))?;
+		content.lines().next().unwrap_or_default().to_string()
+	} else if let Ok(key) = std::env::var(KEY_ENV) {
+		key
+	} else {
+		let key = rpassword::prompt_password("Passphrase: ")?;
+		if confirm && rpassword::prompt_password("Confirm passphrase: ")? != key {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "passphrases don't match"));
+		}
+		key
+	};
+	if key.is_empty() {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "passphrase is empty"));
+	}
+	Ok(PASSPHRASE.get_or_init(|| key))
+}
+
+/// Encryption key, authentication key and password verifier of AES-256 entry
+#[cfg(any(feature = "zip", all(feature = "serve-archive", feature = "piz")))]
+fn derive(passphrase: &str, salt: &[u8]) -> ([u8; 32], [u8; 32], [u8; 2]) {
+	let mut keys = [0; 66];
+	pbkdf2::pbkdf2::<Hmac<Sha1>>(passphrase.as_bytes(), salt, AES_ROUNDS, &mut keys);
+	(keys[..32].try_into().unwrap(), keys[32..64].try_into().unwrap(), [keys[64], keys[65]])
+}
+
+/// AES-256 counter mode of WinZip, little endian counter that start at 1
+#[cfg(any(feature = "zip", all(feature = "serve-archive", feature = "piz")))]
+fn keystream(key: &[u8; 32]) -> ctr::Ctr128LE<aes::Aes256> {
+	use aes::cipher::KeyIvInit;
+	let mut iv = [0; 16];
+	iv[0] = 1;
+	ctr::Ctr128LE::<aes::Aes256>::new(key.into(), &iv.into())
+}
+
+/// Encrypt content of zip entry read from `data` into `out` as WinZip AE-2: salt, password verifier, cipher text and authentication code
+#[cfg(feature = "zip")]
+pub(crate) fn encrypt_aes(data: &mut impl Read, out: &mut impl Write, passphrase: &str) -> io::Result<()> {
+	use aes::cipher::StreamCipher;
+	let salt: [u8; AES_SALT] = rand::random();
+	let (key, mac_key, verifier) = derive(passphrase, &salt);
+	out.write_all(&salt)?;
+	out.write_all(&verifier)?;
+	let mut cipher = keystream(&key);
+	let mut mac = Hmac::<Sha1>::new_from_slice(&mac_key).unwrap();
+	let mut buf = vec![0; 64 << 10];
+	loop {
+		let n = match data.read(&mut buf) {
+			Ok(0) => break,
+			Ok(n) => n,
+			Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(e),
+		};
+		cipher.apply_keystream(&mut buf[..n]);
+		mac.update(&buf[..n]);
+		out.write_all(&buf[..n])?;
+	}
+	out.write_all(&mac.finalize().into_bytes()[..AES_MAC])
+}
+
+/// Encryption key of WinZip AE-2 entry whose passphrase and authentication code were checked
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+pub(crate) struct AesKey([u8; 32]);
+
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+impl AesKey {
+	/// Check passphrase and authentication code of whole `data` of entry, wrong passphrase and tampered content are refused
+	pub(crate) fn open(data: &[u8], passphrase: &str) -> io::Result<Self> {
+		let cipher = aes_cipher(data).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "encrypted entry is truncated"))?;
+		let (key, mac_key, expected) = derive(passphrase, &data[..AES_SALT]);
+		if data[AES_SALT..AES_SALT + 2] != expected {
+			return Err(io::Error::new(io::ErrorKind::PermissionDenied, "wrong passphrase"));
+		}
+		let mut mac = Hmac::<Sha1>::new_from_slice(&mac_key).unwrap();
+		mac.update(&data[cipher.clone()]);
+		mac.verify_truncated_left(&data[cipher.end..]).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "encrypted entry is corrupted"))?;
+		Ok(Self(key))
+	}
+
+	/// Decrypt cipher text read from `inner`, which start at `pos` of it
+	pub(crate) fn reader<R: Read>(&self, inner: R, pos: u64) -> AesReader<R> {
+		use aes::cipher::StreamCipherSeek;
+		let mut cipher = keystream(&self.0);
+		cipher.seek(pos);
+		AesReader { inner, cipher }
+	}
+}
+
+/// Range of cipher text inside data of AES entry, between password verifier and authentication code
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+pub(crate) fn aes_cipher(data: &[u8]) -> Option<std::ops::Range<usize>> {
+	(data.len() >= AES_SALT + 2 + AES_MAC).then(|| AES_SALT + 2..data.len() - AES_MAC)
+}
+
+/// Plain text of AES entry, decrypted while it's read
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+pub(crate) struct AesReader<R> {
+	inner: R,
+	cipher: ctr::Ctr128LE<aes::Aes256>,
+}
+
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+impl<R: Read> Read for AesReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		use aes::cipher::StreamCipher;
+		let n = self.inner.read(buf)?;
+		self.cipher.apply_keystream(&mut buf[..n]);
+		Ok(n)
+	}
+}
+
+/// Whether `passphrase` match password verifier of AES entry, checked once when archive is opened
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+pub(crate) fn check_aes(data: &[u8], passphrase: &str) -> io::Result<()> {
+	let salt = data.get(..AES_SALT).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "encrypted entry is truncated"))?;
+	if data.get(AES_SALT..AES_SALT + 2) != Some(&derive(passphrase, salt).2[..]) {
+		return Err(io::Error::new(io::ErrorKind::PermissionDenied, "wrong passphrase"));
+	}
+	Ok(())
+}
+
+/// Extra field of AE-2 entry with AES-256, `method` is how content was compressed before encryption
+#[cfg(feature = "zip")]
+pub(crate) fn aes_extra(method: u16) -> [u8; 11] {
+	let [lo, hi] = method.to_le_bytes();
+	[0x01, 0x99, 7, 0, 2, 0, b'A', b'E', 3, lo, hi]
+}
+
+/// Encrypt everything written into `inner` with passphrase
+#[cfg(feature = "tar-archive")]
+pub(crate) fn age_writer<W: Write>(inner: W) -> io::Result<age::stream::StreamWriter<W>> {
+	let passphrase = age::secrecy::SecretString::from(passphrase(true)?.to_string());
+	age::Encryptor::with_user_passphrase(passphrase).wrap_output(inner)
+}
+
+/// Decrypt age file made with passphrase, reader can seek when `inner` can
+#[cfg(feature = "tar-archive")]
+pub(crate) fn age_reader<R: Read>(inner: R) -> io::Result<age::stream::StreamReader<R>> {
+	let identity = age::scrypt::Identity::new(age::secrecy::SecretString::from(passphrase(false)?.to_string()));
+	age::Decryptor::new(inner)
+		.and_then(|it| it.decrypt(std::iter::once(&identity as &dyn age::Identity)))
+		.map_err(|e| match e {
+			age::DecryptError::Io(e) => e,
+			age::DecryptError::NoMatchingKeys | age::DecryptError::DecryptionFailed => io::Error::new(io::ErrorKind::PermissionDenied, "wrong passphrase"),
+			e => io::Error::new(io::ErrorKind::InvalidData, e),
+		})
+}
+
+#[cfg(all(test, feature = "zip", feature = "serve-archive", feature = "piz"))]
+mod tests {
+	use std::io::Read;
+
+	use super::*;
+
+	fn encrypt(data: &[u8], passphrase: &str) -> Vec<u8> {
+		let mut out = Vec::new();
+		encrypt_aes(&mut &data[..], &mut out, passphrase).unwrap();
+		out
+	}
+
+	fn decrypt(data: &[u8], passphrase: &str) -> io::Result<Vec<u8>> {
+		let key = AesKey::open(data, passphrase)?;
+		let mut plain = Vec::new();
+		key.reader(&data[aes_cipher(data).unwrap()], 0).read_to_end(&mut plain)?;
+		Ok(plain)
+	}
+
+	#[test]
+	fn round_trip() {
+		let data: Vec<u8> = (0..200_000u32).map(|it| (it % 251) as u8).collect();
+		let sealed = encrypt(&data, "secret");
+		assert_eq!(sealed.len(), AES_SALT + 2 + data.len() + AES_MAC);
+		assert_eq!(decrypt(&sealed, "secret").unwrap(), data);
+		// reader started in the middle decrypt same bytes as whole entry
+		let key = AesKey::open(&sealed, "secret").unwrap();
+		let cipher = aes_cipher(&sealed).unwrap();
+		let mut tail = Vec::new();
+		key.reader(&sealed[cipher.start + 123_457..cipher.end], 123_457).read_to_end(&mut tail).unwrap();
+		assert_eq!(tail, data[123_457..]);
+	}
+
+	#[test]
+	fn wrong_passphrase() {
+		let sealed = encrypt(b"content", "secret");
+		assert_eq!(decrypt(&sealed, "guess").unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+		assert_eq!(check_aes(&sealed, "guess").unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+		check_aes(&sealed, "secret").unwrap();
+	}
+
+	#[test]
+	fn tampered() {
+		let mut sealed = encrypt(b"content that was archived", "secret");
+		sealed[AES_SALT + 5] ^= 1;
+		assert_eq!(decrypt(&sealed, "secret").unwrap_err().kind(), io::ErrorKind::InvalidData);
+		let truncated = encrypt(b"", "secret");
+		assert_eq!(decrypt(&truncated[..truncated.len() - 1], "secret").unwrap_err().kind(), io::ErrorKind::InvalidData);
+	}
+
+	#[test]
+	fn zip_crate_read_compressed() {
+		use crate::cli::CompressFormat;
+		use crate::command::compress::{compress, CompressConfig};
+
+		let dir = std::env::temp_dir().join(format!("archive-it-crypto-{}", std::process::id()));
+		let site = dir.join("site");
+		std::fs::create_dir_all(site.join("docs")).unwrap();
+		let text = "archived page\n".repeat(1000);
+		std::fs::write(site.join("index.GET.html"), &text).unwrap();
+		std::fs::write(site.join("docs/raw.bin"), [7u8; 10]).unwrap();
+		std::env::set_var(KEY_ENV, "secret");
+		let output = dir.join("site.zip");
+		let cfg = CompressConfig { encrypt: true, ..CompressConfig::default() };
+		let res = compress(&site, &CompressFormat::Zip, &output, &cfg);
+		let zip = res.and_then(|()| Ok(zip::ZipArchive::new(std::fs::File::open(&output)?)?));
+		let mut zip = zip.inspect_err(|_| drop(std::fs::remove_dir_all(&dir))).unwrap();
+		let mut read = |name: &str, passphrase: &[u8]| {
+			let index = (0..zip.len()).find(|&it| zip.by_index_raw(it).unwrap().name() == name).unwrap();
+			let mut content = Vec::new();
+			zip.by_index_decrypt(index, passphrase).unwrap().map(|mut it| it.read_to_end(&mut content).map(|_| content))
+		};
+		let page = read("index.GET.html", b"secret");
+		let raw = read("docs/raw.bin", b"secret");
+		let wrong = read("index.GET.html", b"guess");
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(page.unwrap().unwrap(), text.as_bytes());
+		assert_eq!(raw.unwrap().unwrap(), [7; 10]);
+		assert!(!matches!(wrong, Ok(Ok(_))));
+	}
+}
@@ -0,0 +1,292 @@
+use std::borrow::Cow;
+
+use bstr::ByteSlice;
+
+/// `rel` values of `<link>` that point at something the page needs to render
+static SUBRESOURCE_REL: &[&str] = &["stylesheet", "preload", "prefetch", "modulepreload", "icon", "shortcut", "apple-touch-icon", "mask-icon", "manifest"];
+
+/// Collect every url referenced by a html document that browser may fetch to render it
+/// (images and their `srcset` alternatives, scripts, stylesheets, fonts, media posters...).
+/// Returned urls are raw attribute values and still need to be resolved against the page url.
+pub fn html_subresources(buf: &[u8]) -> Vec<String> {
+	let mut out = vec![];
+	for_each_tag(buf, |name, attrs, inner| {
+		let attr = |key: &str| attrs.iter().find(|(k, _)| k.eq_ignore_ascii_case(key.as_bytes())).map(|(_, v)| decode_entities(v));
+		match name.to_ascii_lowercase().as_slice() {
+			b"img" | b"source" => {
+				for key in ["src", "data-src", "srcset", "data-srcset"] {
+					if let Some(v) = attr(key) {
+						if key.ends_with("srcset") {
+							out.extend(srcset(&v));
+						} else {
+							out.push(v.into_owned());
+						}
+					}
+				}
+			}
+			b"script" | b"audio" | b"track" | b"embed" | b"input" => {
+				out.extend(attr("src").map(Cow::into_owned));
+			}
+			b"video" => {
+				out.extend(attr("src").map(Cow::into_owned));
+				out.extend(attr("poster").map(Cow::into_owned));
+			}
+			b"object" => {
+				out.extend(attr("data").map(Cow::into_owned));
+			}
+			b"link" => {
+				let rel = attr("rel").unwrap_or_default().to_ascii_lowercase();
+				if rel.split_ascii_whitespace().any(|it| SUBRESOURCE_REL.contains(&it)) {
+					out.extend(attr("href").map(Cow::into_owned));
+					if let Some(v) = attr("imagesrcset") {
+						out.extend(srcset(&v));
+					}
+				}
+			}
+			b"style" => {
+				out.extend(css_subresources(inner));
+			}
+			_ => {}
+		}
+		if let Some(style) = attr("style") {
+			out.extend(css_subresources(style.as_bytes()));
+		}
+	});
+	out
+}
+
+/// Collect links to other documents (`<a>`, `<area>`, `<iframe>`, `<frame>`) of a html document
+pub fn html_links(buf: &[u8]) -> Vec<String> {
+	let mut out = vec![];
+	for_each_tag(buf, |name, attrs, _| {
+		let key: &[u8] = match name.to_ascii_lowercase().as_slice() {
+			b"a" | b"area" => b"href",
+			b"iframe" | b"frame" => b"src",
+			_ => return,
+		};
+		if let Some((_, v)) = attrs.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)) {
+			out.push(decode_entities(v).into_owned());
+		}
+	});
+	out
+}
+
+/// Collect `url(...)` and `@import` references of a stylesheet
+pub fn css_subresources(buf: &[u8]) -> Vec<String> {
+	let mut out = vec![];
+	let len = buf.len();
+	let mut pos = 0;
+	while pos < len {
+		let rest = &buf[pos..];
+		if rest.starts_with(b"/*") {
+			pos += rest.find(b"*/").map(|it| it + 2).unwrap_or(rest.len());
+		} else if rest.len() >= 4 && rest[..4].eq_ignore_ascii_case(b"url(") {
+			pos += 4;
+			let end = buf[pos..].find_byte(b')').map(|it| it + pos).unwrap_or(len);
+			let value = unquote(buf[pos..end].trim());
+			if !value.is_empty() {
+				out.push(value.to_str_lossy().into_owned());
+			}
+			pos = end;
+		} else if rest.len() >= 7 && rest[..7].eq_ignore_ascii_case(b"@import") {
+			pos += 7;
+			let rest = buf[pos..].trim_start();
+			pos = len - rest.len();
+			// `@import url(...)` will be picked up by next iteration
+			if let Some(q @ (b'"' | b'\'')) = rest.first() {
+				let end = rest[1..].find_byte(*q).map(|it| it + 1).unwrap_or(rest.len());
+				out.push(rest[1..end].to_str_lossy().into_owned());
+				pos += end;
+			}
+		} else {
+			pos += 1;
+		}
+	}
+	out
+}
+
+fn srcset(value: &str) -> impl Iterator<Item=String> + '_ {
+	value.split(',')
+		.filter_map(|it| it.split_ascii_whitespace().next())
+		.map(str::to_string)
+}
+
+fn unquote(value: &[u8]) -> &[u8] {
+	match value {
+		[b'"', inner @ .., b'"'] | [b'\'', inner @ .., b'\''] => inner,
+		_ => value,
+	}
+}
+
+fn decode_entities(value: &[u8]) -> Cow<'_, str> {
+	let value = value.to_str_lossy();
+	if value.contains('&') {
+		Cow::Owned(value.replace("&amp;", "&").replace("&quot;", "\"").replace("&#39;", "'"))
+	} else {
+		value
+	}
+}
+
+/// Walk every start tag of a html document, `inner` is content of raw text element (`<style>`, `<script>`)
+pub fn for_each_tag<'a>(buf: &'a [u8], mut f: impl FnMut(&'a [u8], &[(&'a [u8], &'a [u8])], &'a [u8])) {
+	let len = buf.len();
+	let mut pos = 0;
+	let mut attrs = Vec::new();
+	while let Some(start) = buf[pos..].find_byte(b'<').map(|it| it + pos) {
+		pos = start + 1;
+		if buf[pos..].starts_with(b"!--") {
+			pos += buf[pos..].find(b"-->").map(|it| it + 3).unwrap_or(len - pos);
+			continue;
+		}
+		if !buf.get(pos).is_some_and(u8::is_ascii_alphabetic) { continue; }
+		let name_end = buf[pos..].iter().position(|it| it.is_ascii_whitespace() || matches!(it, b'/' | b'>')).map(|it| it + pos).unwrap_or(len);
+		let name = &buf[pos..name_end];
+		pos = name_end;
+		attrs.clear();
+		loop {
+			while pos < len && (buf[pos].is_ascii_whitespace() || buf[pos] == b'/') { pos += 1; }
+			if pos >= len || buf[pos] == b'>' { break; }
+			let key_start = pos;
+			while pos < len && !buf[pos].is_ascii_whitespace() && !matches!(buf[pos], b'=' | b'>' | b'/') { pos += 1; }
+			let key = &buf[key_start..pos];
+			while pos < len && buf[pos].is_ascii_whitespace() { pos += 1; }
+			if pos < len && buf[pos] == b'=' {
+				pos += 1;
+				while pos < len && buf[pos].is_ascii_whitespace() { pos += 1; }
+				let value = match buf.get(pos) {
+					Some(q @ (b'"' | b'\'')) => {
+						let end = buf[pos + 1..].find_byte(*q).map(|it| it + pos + 1).unwrap_or(len);
+						let value = &buf[pos + 1..end];
+						pos = (end + 1).min(len);
+						value
+					}
+					_ => {
+						let value_start = pos;
+						while pos < len && !buf[pos].is_ascii_whitespace() && buf[pos] != b'>' { pos += 1; }
+						&buf[value_start..pos]
+					}
+				};
+				attrs.push((key, value));
+			} else if key.is_empty() {
+				pos += 1;
+			} else {
+				attrs.push((key, &b""[..]));
+			}
+		}
+		pos = (pos + 1).min(len);
+		let mut inner: &[u8] = &[];
+		if name.eq_ignore_ascii_case(b"style") || name.eq_ignore_ascii_case(b"script") {
+			let end = buf[pos..].find_iter(b"</")
+				.map(|it| it + pos)
+				.find(|&it| buf.get(it + 2..it + 2 + name.len()).is_some_and(|it| it.eq_ignore_ascii_case(name)))
+				.unwrap_or(len);
+			inner = &buf[pos..end];
+			pos = end;
+		}
+		f(name, &attrs, inner);
+	}
+}
+
+/// Content of `<title>` of html document
+pub fn html_title(buf: &[u8]) -> Option<String> {
+	let start = buf.find_iter(b"<").find(|&it| buf.get(it + 1..it + 6).is_some_and(|it| it.eq_ignore_ascii_case(b"title")))?;
+	let start = buf[start..].find_byte(b'>')? + start + 1;
+	let end = buf[start..].find(b"</").map_or(buf.len(), |it| it + start);
+	let title = text_content(&buf[start..end]).replace('\n', " ");
+	(!title.is_empty()).then_some(title)
+}
+
+/// Text content of html document without markup and `<script>`/`<style>`,
+/// every text node is collapsed into its own line
+pub fn text_content(buf: &[u8]) -> String {
+	let len = buf.len();
+	let mut text = Vec::with_capacity(len / 2);
+	let mut pos = 0;
+	while pos < len {
+		if buf[pos] != b'<' {
+			text.push(buf[pos]);
+			pos += 1;
+			continue;
+		}
+		let rest = &buf[pos + 1..];
+		text.push(b'\n');
+		if rest.starts_with(b"!--") {
+			// `rest` start after `<`, so `-->` end 4 bytes past where it's found
+			pos += rest.find(b"-->").map(|it| it + 4).unwrap_or(rest.len() + 1);
+			continue;
+		}
+		let raw = [&b"script"[..], b"style"].into_iter()
+			.find(|name| rest.get(..name.len()).is_some_and(|it| it.eq_ignore_ascii_case(name)));
+		pos += rest.find_byte(b'>').map(|it| it + 1).unwrap_or(rest.len()) + 1;
+		if let Some(name) = raw {
+			pos = buf[pos..].find_iter(b"</")
+				.map(|it| it + pos)
+				.find(|&it| buf.get(it + 2..it + 2 + name.len()).is_some_and(|it| it.eq_ignore_ascii_case(name)))
+				.unwrap_or(len);
+		}
+	}
+	let text = String::from_utf8_lossy(&text)
+		.replace("&nbsp;", " ")
+		.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&quot;", "\"")
+		.replace("&#39;", "'")
+		.replace("&amp;", "&");
+	text.lines()
+		.map(|it| it.split_whitespace().collect::<Vec<_>>().join(" "))
+		.filter(|it| !it.is_empty())
+		.collect::<Vec<_>>()
+		.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn html() {
+		let page = br#"<html><head>
+			<link rel="stylesheet" href="/main.css"><link rel="canonical" href="/other">
+			<style>body { background: url('/bg.png') }</style>
+			<!-- <img src="/commented.png"> -->
+			</head><body>
+			<img src="/a.png" srcset="/a-2x.png 2x, /a-3x.png 3x">
+			<img data-src="/lazy.png" data-srcset="/lazy-2x.png 2x">
+			<div style="background-image: url(/div.png)"></div>
+			<script src="/app.js?v=1&amp;x=2"></script>
+			<a href="/page.html">page</a>
+		</body></html>"#;
+		assert_eq!(html_subresources(page), [
+			"/main.css", "/bg.png", "/a.png", "/a-2x.png", "/a-3x.png",
+			"/lazy.png", "/lazy-2x.png", "/div.png", "/app.js?v=1&x=2",
+		]);
+		assert_eq!(html_links(page), ["/page.html"]);
+	}
+
+	#[test]
+	fn css() {
+		let css = br#"@import "print.css" print; @IMPORT url(theme.css);
+			/* url(/commented.png) @import "commented.css"; */
+			.a { background: url( "/a.png" ) } .b { src: URL('/font.woff2') format('woff2') }
+			.c { background: url()}url(end.png)"#;
+		assert_eq!(css_subresources(css), ["print.css", "theme.css", "/a.png", "/font.woff2", "end.png"]);
+		// references that end exactly at end of buffer
+		assert_eq!(css_subresources(b"url(x)"), ["x"]);
+		assert!(css_subresources(b"url(").is_empty());
+		assert!(css_subresources(b"@import").is_empty());
+	}
+
+	#[test]
+	fn tags() {
+		let mut tags = vec![];
+		for_each_tag(b"<p class=a id='b' hidden>text</p><!-- <i> --><script>if (a<b) {}</script><br/>", |name, attrs, inner| {
+			let attrs: Vec<_> = attrs.iter().map(|(k, v)| format!("{}={}", k.to_str_lossy(), v.to_str_lossy())).collect();
+			tags.push((name.to_str_lossy().into_owned(), attrs, inner.to_str_lossy().into_owned()));
+		});
+		assert_eq!(tags, [
+			("p".to_string(), vec!["class=a".to_string(), "id=b".to_string(), "hidden=".to_string()], String::new()),
+			("script".to_string(), vec![], "if (a<b) {}".to_string()),
+			("br".to_string(), vec![], String::new()),
+		]);
+	}
+}
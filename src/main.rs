@@ -5,6 +5,21 @@ use crate::cli::Config;
 mod common;
 pub(crate) mod macros;
 mod utils;
+mod links;
+mod archive;
+mod conditional;
+mod browse;
+mod search;
+mod matcher;
+#[cfg(feature = "tar-archive")]
+mod seekable;
+#[cfg(feature = "encrypt")]
+mod crypto;
+#[cfg(all(feature = "optimize", any(feature = "zip", feature = "tar-archive")))]
+mod optimize;
+mod warc;
+#[cfg(any(feature = "zip", feature = "tar-archive", feature = "serve-archive"))]
+mod volume;
 pub(crate) mod cli;
 mod large_state;
 mod maybe_async;
@@ -0,0 +1,142 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::Path;
+
+use percent_encoding::percent_decode_str;
+
+use crate::cli::MatchStrategy;
+use crate::utils::wildcard_match;
+
+/// Find archived query variant of a resource when request doesn't match stored name exactly
+pub(crate) struct Matcher {
+	pub strategies: Vec<MatchStrategy>,
+	/// wildcard patterns of params that [`MatchStrategy::IgnoreParams`] disregard
+	pub ignore: Vec<String>,
+}
+
+impl Matcher {
+	/// Pick one of `names` (files in same folder) that store variant of `base` (name of resource without query),
+	/// return it with strategy that matched
+	pub(crate) fn find<'a>(&self, base: &str, query: &HashMap<String, String>, names: impl Iterator<Item=&'a str>) -> Option<(&'a str, MatchStrategy)> {
+		let mut variants: Vec<_> = names.filter_map(|name| Some((name, variant_query(base, name, query)?))).collect();
+		// keep choice stable between requests
+		variants.sort_unstable_by_key(|(name, _)| *name);
+		let ignored = |k: &String| self.ignore.iter().any(|it| wildcard_match(it, k));
+		for strategy in &self.strategies {
+			let found = match strategy {
+				MatchStrategy::Reordered => variants.iter().find(|(_, q)| q == query),
+				MatchStrategy::IgnoreParams => {
+					let wanted: HashMap<_, _> = query.iter().filter(|(k, _)| !ignored(k)).collect();
+					variants.iter().find(|(_, q)| q.iter().filter(|(k, _)| !ignored(k)).collect::<HashMap<_, _>>() == wanted)
+				}
+				MatchStrategy::NoQuery => variants.iter().find(|(_, q)| q.is_empty()),
+				MatchStrategy::Closest => variants.iter()
+					.map(|it| (it, it.1.iter().filter(|(k, v)| query.get(*k) == Some(*v)).count()))
+					// variant that share nothing isn't close, later strategies or not found page get a chance
+					.filter(|(_, shared)| *shared > 0)
+					.min_by_key(|((_, q), shared)| (Reverse(*shared), q.len()))
+					.map(|(it, _)| it),
+			};
+			if let Some((name, _)) = found {
+				return Some((name, *strategy));
+			}
+		}
+		None
+	}
+}
+
+/// Query stored in `name` if it is variant of `base` made by [`crate::common::normalize_url_path`].
+/// Param without value look like any hyphenated name (`my-page.html` for `my.html`), so it's only accepted
+/// when request has that param too
+fn variant_query(base: &str, name: &str, query: &HashMap<String, String>) -> Option<HashMap<String, String>> {
+	if name == base { return Some(HashMap::new()); }
+	let path = Path::new(base);
+	let stem = path.file_stem().unwrap_or(path.as_os_str()).to_str()?;
+	let mut params = name.strip_prefix(stem)?;
+	if let Some(ext) = path.extension() {
+		params = params.strip_suffix(ext.to_str()?)?.strip_suffix('.')?;
+	}
+	// key and value are percent encoded so `-` and `=` only appear as separator
+	let params = params.strip_prefix('-')?;
+	let decode = |it: &str| percent_decode_str(it).decode_utf8_lossy().into_owned();
+	params.split('-').map(|it| {
+		// empty value is written without `=`
+		let (k, v) = match it.split_once('=') {
+			Some((_, "")) => return None,
+			Some(it) => it,
+			None => (it, ""),
+		};
+		if k.is_empty() || !is_encoded(k) || !is_encoded(v) { return None; }
+		let k = decode(k);
+		if v.is_empty() && !query.contains_key(&k) { return None; }
+		Some((k, decode(v)))
+	}).collect()
+}
+
+/// `value` is made only of what percent encoding of every non alphanumeric character produce
+fn is_encoded(value: &str) -> bool {
+	let bytes = value.as_bytes();
+	let mut i = 0;
+	while i < bytes.len() {
+		match bytes[i] {
+			b'%' if bytes.get(i + 1..i + 3).is_some_and(|it| it.iter().all(u8::is_ascii_hexdigit)) => i += 3,
+			it if it.is_ascii_alphanumeric() => i += 1,
+			_ => return false,
+		}
+	}
+	true
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn query(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+		pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+	}
+
+	fn matcher(strategies: &[MatchStrategy], ignore: &[&str]) -> Matcher {
+		Matcher { strategies: strategies.to_vec(), ignore: ignore.iter().map(|it| it.to_string()).collect() }
+	}
+
+	#[test]
+	fn exact_and_reordered() {
+		let names = ["page.html", "page-a=1-b=2.html", "page-a=1.html"];
+		let m = matcher(&[MatchStrategy::Reordered, MatchStrategy::NoQuery], &[]);
+		assert_eq!(m.find("page.html", &query(&[("b", "2"), ("a", "1")]), names.into_iter()), Some(("page-a=1-b=2.html", MatchStrategy::Reordered)));
+		assert_eq!(m.find("page.html", &query(&[]), names.into_iter()), Some(("page.html", MatchStrategy::Reordered)));
+		// percent encoded key and value are decoded
+		assert_eq!(m.find("page.html", &query(&[("q", "a b")]), ["page-q=a%20b.html"].into_iter()), Some(("page-q=a%20b.html", MatchStrategy::Reordered)));
+	}
+
+	#[test]
+	fn no_query() {
+		let m = matcher(&[MatchStrategy::NoQuery], &[]);
+		let names = ["page-a=1.html", "pageunknown_ext", "page.html"];
+		assert_eq!(m.find("page.html", &query(&[("a", "2")]), names.into_iter()), Some(("page.html", MatchStrategy::NoQuery)));
+		assert_eq!(m.find("page.html", &query(&[("a", "2")]), names[..2].iter().copied()), None);
+	}
+
+	#[test]
+	fn ignore_params_and_closest() {
+		let names = ["page-a=1-utm%5Fsource=x.html", "page-a=2-b=1.html", "page-a=2-b=3-c=4.html"];
+		let m = matcher(&[MatchStrategy::IgnoreParams], &["utm_*"]);
+		assert_eq!(m.find("page.html", &query(&[("a", "1"), ("utm_source", "y")]), names.into_iter()), Some(("page-a=1-utm%5Fsource=x.html", MatchStrategy::IgnoreParams)));
+		let m = matcher(&[MatchStrategy::Closest], &[]);
+		assert_eq!(m.find("page.html", &query(&[("a", "2"), ("b", "3")]), names.into_iter()), Some(("page-a=2-b=3-c=4.html", MatchStrategy::Closest)));
+		// shorter variant win a tie
+		assert_eq!(m.find("page.html", &query(&[("a", "2")]), names.into_iter()), Some(("page-a=2-b=1.html", MatchStrategy::Closest)));
+		assert_eq!(m.find("page.html", &query(&[("z", "1")]), names.into_iter()), None);
+	}
+
+	#[test]
+	fn hyphenated_name_is_not_variant() {
+		let m = matcher(&[MatchStrategy::IgnoreParams, MatchStrategy::Closest], &["*"]);
+		let names = ["my-page.html", "my-old_page.html", "my-a=.html", "my--a=1.html"];
+		assert_eq!(m.find("my.html", &query(&[("v", "1")]), names.into_iter()), None);
+		assert_eq!(m.find("my.html", &query(&[("a", "1")]), names.into_iter()), None);
+		// unless request has that param without value
+		let m = matcher(&[MatchStrategy::Reordered], &[]);
+		assert_eq!(m.find("my.html", &query(&[("page", "")]), names.into_iter()), Some(("my-page.html", MatchStrategy::Reordered)));
+	}
+}
@@ -0,0 +1,295 @@
+//! Lossless size reduction of archived files before they're compressed: text is minified and images recompressed
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::sync::Mutex;
+
+use tracing::info;
+
+use crate::command::compress::Preset;
+
+/// Content that can be made smaller without changing what browser does with it
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+	Html,
+	Css,
+	Js,
+	Json,
+	Png,
+	Jpeg,
+}
+
+impl Kind {
+	/// Kind of file `name` by its mime type, `head` is beginning of its content that tell type of files forward stored without extension
+	fn of(name: &str, head: &[u8]) -> Option<Self> {
+		let guess = mime_guess::from_path(name).first();
+		let Some(mime) = guess.as_ref().map(|it| it.essence_str()) else {
+			let at = |magic: &[u8]| head.starts_with(magic);
+			let text = head.trim_ascii_start().to_ascii_lowercase();
+			return match () {
+				_ if at(b"\x89PNG\r\n\x1A\n") => Some(Kind::Png),
+				_ if at(b"\xFF\xD8\xFF") => Some(Kind::Jpeg),
+				_ if text.starts_with(b"<!doctype html") || text.starts_with(b"<html") => Some(Kind::Html),
+				_ => None,
+			};
+		};
+		Some(match mime {
+			"text/html" | "application/xhtml+xml" => Kind::Html,
+			"text/css" => Kind::Css,
+			"text/javascript" | "application/javascript" | "application/x-javascript" => Kind::Js,
+			"application/json" => Kind::Json,
+			_ if mime.ends_with("+json") => Kind::Json,
+			"image/png" => Kind::Png,
+			"image/jpeg" => Kind::Jpeg,
+			_ => return None,
+		})
+	}
+}
+
+impl fmt::Display for Kind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Kind::Html => "html",
+			Kind::Css => "css",
+			Kind::Js => "js",
+			Kind::Json => "json",
+			Kind::Png => "png",
+			Kind::Jpeg => "jpeg",
+		})
+	}
+}
+
+/// Files of one kind and their size before and after
+#[derive(Default)]
+struct Saved {
+	files: usize,
+	before: u64,
+	after: u64,
+}
+
+/// Optimize files while they're compressed and count what it saved
+pub struct Optimizer {
+	preset: Preset,
+	saved: Mutex<BTreeMap<Kind, Saved>>,
+}
+
+impl Optimizer {
+	/// `preset` pick how hard images are recompressed
+	pub fn new(preset: Preset) -> Self {
+		Self { preset, saved: Mutex::default() }
+	}
+
+	/// Content of `path` made smaller, `None` when it isn't a kind that can be and should be read as it is.
+	/// Original content is kept when optimizing fails or doesn't shrink it
+	pub(crate) fn optimized(&self, path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+		let mut f = File::open(path)?;
+		let mut data = Vec::with_capacity(16);
+		(&mut f).take(16).read_to_end(&mut data)?;
+		let Some(kind) = Kind::of(name, &data) else { return Ok(None) };
+		f.read_to_end(&mut data)?;
+		let before = data.len() as u64;
+		// minifiers panic on some malformed input, such file is kept as it is
+		let data = match std::panic::catch_unwind(AssertUnwindSafe(|| self.optimize(kind, &data))) {
+			Ok(Some(it)) if it.len() < data.len() => it,
+			_ => data,
+		};
+		let mut saved = self.saved.lock().unwrap();
+		let saved = saved.entry(kind).or_default();
+		saved.files += 1;
+		saved.before += before;
+		saved.after += data.len() as u64;
+		Ok(Some(data))
+	}
+
+	fn optimize(&self, kind: Kind, data: &[u8]) -> Option<Vec<u8>> {
+		match kind {
+			Kind::Html => Some(minify_html::minify(&strip_inline_source_maps(data), &minify_html::Cfg {
+				minify_css: true,
+				minify_js: true,
+				// omitted tags are implied by parser, but scripts that look for them wouldn't find them
+				keep_closing_tags: true,
+				keep_html_and_head_opening_tags: true,
+				..minify_html::Cfg::spec_compliant()
+			})),
+			Kind::Css => {
+				let text = strip_source_map(std::str::from_utf8(data).ok()?);
+				Some(minify_css(text).unwrap_or_else(|| text.as_bytes().to_vec()))
+			}
+			Kind::Js => {
+				let text = strip_source_map(std::str::from_utf8(data).ok()?);
+				Some(minify_js(text.as_bytes()).unwrap_or_else(|| text.as_bytes().to_vec()))
+			}
+			Kind::Json => minify_json(data),
+			Kind::Png => {
+				let level = match self.preset { Preset::Fast => 1, Preset::Default => 2, Preset::Best => 4 };
+				oxipng::optimize_from_memory(data, &oxipng::Options::from_preset(level)).ok()
+			}
+			Kind::Jpeg => recompress_jpeg(data),
+		}
+	}
+
+	/// Log size of optimized files by kind
+	pub(crate) fn report(&self) {
+		for (kind, saved) in self.saved.lock().unwrap().iter() {
+			let percent = ((saved.before - saved.after) * 100).checked_div(saved.before).unwrap_or(0);
+			info!("optimized {} {kind} files, {} KiB -> {} KiB (-{percent}%)", saved.files, saved.before >> 10, saved.after >> 10);
+		}
+	}
+}
+
+/// Remove `sourceMappingURL` comment that end script or style sheet, maps aren't archived along with files that point to them.
+/// Only last line is looked at, same text anywhere else can be part of string
+fn strip_source_map(text: &str) -> &str {
+	let trimmed = text.trim_end();
+	let start = trimmed.rfind('\n').map_or(0, |it| it + 1);
+	let line = trimmed[start..].trim_start();
+	let is_map = match line.get(..3) {
+		Some("//#" | "//@") => line[3..].trim_start().starts_with("sourceMappingURL="),
+		Some("/*#" | "/*@") => line[3..].trim_start().starts_with("sourceMappingURL=") && line.find("*/") == Some(line.len() - 2),
+		_ => false,
+	};
+	if is_map { &text[..start] } else { text }
+}
+
+/// Html with source map comments that end its inline scripts and styles removed, minifier keep them otherwise
+fn strip_inline_source_maps(data: &[u8]) -> std::borrow::Cow<'_, [u8]> {
+	let Ok(html) = std::str::from_utf8(data) else { return data.into() };
+	// same length and offsets as html
+	let lower = html.to_ascii_lowercase();
+	let mut out = String::with_capacity(html.len());
+	let mut pos = 0;
+	while let Some((tag, open)) = ["<script", "<style"].into_iter().filter_map(|tag| Some((tag, pos + lower[pos..].find(tag)?))).min_by_key(|it| it.1) {
+		let Some(start) = lower[open..].find('>').map(|it| open + it + 1) else { break };
+		let Some(end) = lower[start..].find(&format!("</{}", &tag[1..])).map(|it| start + it) else { break };
+		out.push_str(&html[pos..start]);
+		out.push_str(strip_source_map(&html[start..end]));
+		pos = end;
+	}
+	out.push_str(&html[pos..]);
+	out.into_bytes().into()
+}
+
+fn minify_css(text: &str) -> Option<Vec<u8>> {
+	use lightningcss::stylesheet::{MinifyOptions, ParserOptions, PrinterOptions, StyleSheet};
+	let mut sheet = StyleSheet::parse(text, ParserOptions::default()).ok()?;
+	sheet.minify(MinifyOptions::default()).ok()?;
+	let css = sheet.to_css(PrinterOptions { minify: true, ..PrinterOptions::default() }).ok()?;
+	Some(css.code.into_bytes())
+}
+
+/// Minify script, either classic one or module
+fn minify_js(code: &[u8]) -> Option<Vec<u8>> {
+	use minify_js::TopLevelMode;
+	[TopLevelMode::Global, TopLevelMode::Module].into_iter().find_map(|mode| {
+		let session = minify_js::Session::new();
+		let mut out = Vec::new();
+		minify_js::minify(&session, mode, code, &mut out).ok().map(|()| out)
+	})
+}
+
+/// Drop whitespace between tokens of valid JSON, strings and numbers are kept byte for byte
+fn minify_json(data: &[u8]) -> Option<Vec<u8>> {
+	serde_json::from_slice::<serde::de::IgnoredAny>(data).ok()?;
+	let mut out = Vec::with_capacity(data.len());
+	let (mut string, mut escaped) = (false, false);
+	for &b in data {
+		if escaped {
+			escaped = false;
+		} else if string {
+			match b {
+				b'\\' => escaped = true,
+				b'"' => string = false,
+				_ => {}
+			}
+		} else if b == b'"' {
+			string = true;
+		} else if b.is_ascii_whitespace() {
+			continue;
+		}
+		out.push(b);
+	}
+	Some(out)
+}
+
+/// Rewrite JPEG with optimized huffman tables and progressive scans like `jpegtran -copy all`,
+/// DCT coefficients and markers are copied so decoded image is the same
+fn recompress_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+	use mozjpeg_sys::*;
+
+	/// Decoder, encoder and output buffer of libjpeg, released even when it unwinds out of an error
+	struct Jpeg {
+		err: jpeg_error_mgr,
+		src: jpeg_decompress_struct,
+		dst: jpeg_compress_struct,
+		out: *mut u8,
+		out_len: std::ffi::c_ulong,
+	}
+
+	impl Drop for Jpeg {
+		fn drop(&mut self) {
+			unsafe {
+				jpeg_destroy_compress(&mut self.dst);
+				jpeg_destroy_decompress(&mut self.src);
+				libc::free(self.out.cast());
+			}
+		}
+	}
+
+	extern "C-unwind" fn error_exit(_: &mut jpeg_common_struct) {
+		// doesn't go through panic hook, caught below
+		std::panic::resume_unwind(Box::new(()));
+	}
+
+	extern "C-unwind" fn emit_message(cinfo: &mut jpeg_common_struct, level: std::ffi::c_int) {
+		if level < 0 {
+			unsafe { (*cinfo.err).num_warnings += 1 };
+		}
+	}
+
+	let data_len = std::ffi::c_ulong::try_from(data.len()).ok()?;
+	std::panic::catch_unwind(|| unsafe {
+		// libjpeg keep pointer to error manager, it must not move
+		let mut jpeg: Box<Jpeg> = Box::new(std::mem::zeroed());
+		jpeg_std_error(&mut jpeg.err);
+		jpeg.err.error_exit = Some(error_exit);
+		jpeg.err.emit_message = Some(emit_message);
+		let jpeg = &mut *jpeg;
+		jpeg.src.common.err = &mut jpeg.err;
+		jpeg.dst.common.err = &mut jpeg.err;
+		jpeg_create_decompress(&mut jpeg.src);
+		jpeg_create_compress(&mut jpeg.dst);
+
+		jpeg_mem_src(&mut jpeg.src, data.as_ptr(), data_len);
+		jpeg_save_markers(&mut jpeg.src, jpeg_marker::COM as i32, 0xFFFF);
+		for i in 0..16 {
+			jpeg_save_markers(&mut jpeg.src, jpeg_marker::APP0 as i32 + i, 0xFFFF);
+		}
+		jpeg_read_header(&mut jpeg.src, 1);
+		let coefficients = jpeg_read_coefficients(&mut jpeg.src);
+		jpeg_copy_critical_parameters(&jpeg.src, &mut jpeg.dst);
+		jpeg.dst.optimize_coding = 1;
+		jpeg_simple_progression(&mut jpeg.dst);
+		jpeg_mem_dest(&mut jpeg.dst, &mut jpeg.out, &mut jpeg.out_len);
+		jpeg_write_coefficients(&mut jpeg.dst, coefficients);
+		let mut marker = jpeg.src.marker_list;
+		while let Some(it) = marker.as_ref() {
+			let payload = std::slice::from_raw_parts(it.data, it.data_length as usize);
+			// encoder wrote its own JFIF and Adobe markers already
+			let written = it.marker == 0xE0 && jpeg.dst.write_JFIF_header != 0 && payload.starts_with(b"JFIF\0")
+				|| it.marker == 0xEE && jpeg.dst.write_Adobe_marker != 0 && payload.starts_with(b"Adobe");
+			if !written {
+				jpeg_write_marker(&mut jpeg.dst, it.marker as i32, it.data, it.data_length);
+			}
+			marker = it.next;
+		}
+		jpeg_finish_compress(&mut jpeg.dst);
+		jpeg_finish_decompress(&mut jpeg.src);
+		// corrupt data is reported as warning, output of it might not decode the same
+		(jpeg.err.num_warnings == 0).then(|| std::slice::from_raw_parts(jpeg.out, jpeg.out_len as usize).to_vec())
+	}).ok().flatten()
+}
@@ -0,0 +1,158 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::archive::dir_entries;
+use crate::command::watch::SNAPSHOT_DIR;
+use crate::common::{is_typed_sibling, UNKNOWN_EXT};
+use crate::links::{html_title, text_content};
+
+/// File inside archive folder that keep search index, it's a normal file so it will be compressed along
+pub(crate) static INDEX_FILE: &str = ".archive-index.json";
+
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// Inverted index of text of archived html pages
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct SearchIndex {
+	docs: Vec<Doc>,
+	/// term -> (doc id, term frequency)
+	terms: BTreeMap<String, Vec<(u32, u32)>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Doc {
+	path: String,
+	title: String,
+	/// beginning of text content, shown in search result
+	excerpt: String,
+	size: u64,
+	modified: u64,
+	/// number of terms
+	len: u32,
+}
+
+pub(crate) struct SearchResult<'a> {
+	pub path: &'a str,
+	pub title: &'a str,
+	pub excerpt: &'a str,
+	pub score: f64,
+}
+
+impl SearchIndex {
+	/// Index every html page of archive folder, pages that didn't change since `previous` was built
+	/// are carried over without reading them again. Return index and number of (re)indexed pages
+	pub(crate) fn build(root: &Path, previous: Option<SearchIndex>) -> (Self, usize) {
+		let previous = previous.unwrap_or_default();
+		let known: HashMap<&str, (u32, &Doc)> = previous.docs.iter()
+			.enumerate()
+			.map(|(id, it)| (it.path.as_str(), (id as u32, it)))
+			.collect();
+		let mut index = SearchIndex::default();
+		// old doc id -> new doc id
+		let mut carried = HashMap::new();
+		let mut indexed = 0;
+		for entry in dir_entries(root, root) {
+			if entry.path.starts_with(SNAPSHOT_DIR) || entry.path == INDEX_FILE || !is_page(root, &entry.path) { continue; }
+			let modified = entry.modified
+				.and_then(|it| it.duration_since(UNIX_EPOCH).ok())
+				.map_or(0, |it| it.as_secs());
+			let id = index.docs.len() as u32;
+			if let Some((old, doc)) = known.get(entry.path.as_str()) {
+				if doc.size == entry.size && doc.modified == modified {
+					carried.insert(*old, id);
+					index.docs.push((*doc).clone());
+					continue;
+				}
+			}
+			let Ok(content) = std::fs::read(root.join(&entry.path)) else { continue; };
+			let text = text_content(&content);
+			let mut freq: HashMap<String, u32> = HashMap::new();
+			let mut len = 0;
+			for term in tokenize(&text) {
+				*freq.entry(term).or_default() += 1;
+				len += 1;
+			}
+			for (term, tf) in freq {
+				index.terms.entry(term).or_default().push((id, tf));
+			}
+			index.docs.push(Doc {
+				title: html_title(&content).unwrap_or_else(|| entry.path.clone()),
+				excerpt: text.replace('\n', " ").chars().take(240).collect(),
+				path: entry.path,
+				size: entry.size,
+				modified,
+				len,
+			});
+			indexed += 1;
+		}
+		for (term, postings) in previous.terms {
+			let postings: Vec<_> = postings.into_iter()
+				.filter_map(|(old, tf)| Some((*carried.get(&old)?, tf)))
+				.collect();
+			if !postings.is_empty() {
+				index.terms.entry(term).or_default().extend(postings);
+			}
+		}
+		(index, indexed)
+	}
+
+	pub(crate) fn search(&self, query: &str, limit: usize) -> Vec<SearchResult<'_>> {
+		if self.docs.is_empty() { return vec![]; }
+		let n = self.docs.len() as f64;
+		let avg = self.docs.iter().map(|it| it.len as f64).sum::<f64>() / n;
+		let mut scores: HashMap<u32, f64> = HashMap::new();
+		let terms: Vec<String> = tokenize(query).collect();
+		for term in &terms {
+			let Some(postings) = self.terms.get(term) else { continue; };
+			let idf = ((n - postings.len() as f64 + 0.5) / (postings.len() as f64 + 0.5) + 1.0).ln();
+			for &(id, tf) in postings {
+				let doc = &self.docs[id as usize];
+				let tf = tf as f64;
+				let mut score = idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * doc.len as f64 / avg.max(1.0)));
+				if doc.title.to_lowercase().contains(term.as_str()) {
+					score *= 2.0;
+				}
+				*scores.entry(id).or_default() += score;
+			}
+		}
+		let mut results: Vec<_> = scores.into_iter()
+			.map(|(id, score)| {
+				let doc = &self.docs[id as usize];
+				SearchResult { path: &doc.path, title: &doc.title, excerpt: &doc.excerpt, score }
+			})
+			.collect();
+		results.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.path.cmp(b.path)));
+		results.truncate(limit);
+		results
+	}
+}
+
+fn tokenize(text: &str) -> impl Iterator<Item=String> + '_ {
+	text.split(|it: char| !it.is_alphanumeric())
+		.filter(|it| (2..=40).contains(&it.chars().count()))
+		.map(str::to_lowercase)
+}
+
+/// Html page that worth indexing, `unknown_ext` copy is skipped when forward also stored it with real extension
+fn is_page(root: &Path, path: &str) -> bool {
+	if let Some(stem) = path.strip_suffix(UNKNOWN_EXT) {
+		let file = root.join(path);
+		let has_sibling = file.parent()
+			.and_then(|dir| dir.read_dir().ok())
+			.is_some_and(|it| it.flatten().any(|it| {
+				let name = it.file_name().to_string_lossy().into_owned();
+				is_typed_sibling(stem.rsplit('/').next().unwrap_or(stem), &name)
+			}));
+		if has_sibling { return false; }
+		let mut head = [0; 1024];
+		let n = std::fs::File::open(file).and_then(|mut it| std::io::Read::read(&mut it, &mut head)).unwrap_or(0);
+		let head = String::from_utf8_lossy(&head[..n]).to_lowercase();
+		head.contains("<html") || head.contains("<!doctype html")
+	} else {
+		matches!(Path::new(path).extension().and_then(|it| it.to_str()), Some("html" | "htm" | "xhtml"))
+	}
+}
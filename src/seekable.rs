@@ -0,0 +1,225 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+
+/// Amount of uncompressed data in each independent frame
+const FRAME_SIZE: usize = 1 << 20;
+const SKIPPABLE_MAGIC: u32 = 0x184D2A5E;
+const SEEKABLE_MAGIC: u32 = 0x8F92EAB1;
+/// number of frames, descriptor and magic at end of seek table
+const FOOTER_SIZE: usize = 9;
+
+/// Content that can be read from arbitrary offset without shared cursor
+pub(crate) trait ReadAt: Send + Sync {
+	fn len(&self) -> u64;
+
+	/// Read up to `len` bytes at `offset`, less only at end of content
+	fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>>;
+}
+
+/// Uncompressed file, volumes of split archive or decrypted stream, anything that can seek
+pub(crate) struct PlainFile<R>(Mutex<R>, u64);
+
+impl<R: Read + Seek> PlainFile<R> {
+	pub(crate) fn new(mut reader: R) -> io::Result<Self> {
+		let len = reader.seek(SeekFrom::End(0))?;
+		Ok(Self(Mutex::new(reader), len))
+	}
+}
+
+impl<R: Read + Seek + Send> ReadAt for PlainFile<R> {
+	fn len(&self) -> u64 {
+		self.1
+	}
+
+	fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+		let mut file = self.0.lock().unwrap();
+		file.seek(SeekFrom::Start(offset))?;
+		let mut buf = Vec::with_capacity(len);
+		(&mut *file).take(len as u64).read_to_end(&mut buf)?;
+		Ok(buf)
+	}
+}
+
+/// Writer of [seekable zstd format](https://github.com/facebook/zstd/blob/dev/contrib/seekable_format/zstd_seekable_compression_format.md),
+/// content is split into independently compressed frames and sizes of every frame are appended as skippable frame
+pub(crate) struct SeekableWriter<W: Write> {
+	inner: W,
+	/// uncompressed frames waiting to be compressed together, last one is being filled
+	batch: Vec<Vec<u8>>,
+	/// compressed and decompressed size of written frames
+	frames: Vec<(u32, u32)>,
+	level: i32,
+	/// number of frames compressed in parallel
+	jobs: usize,
+}
+
+impl<W: Write> SeekableWriter<W> {
+	pub(crate) fn new(inner: W, level: i32, jobs: usize) -> Self {
+		Self { inner, batch: vec![Vec::with_capacity(FRAME_SIZE)], frames: vec![], level, jobs: jobs.max(1) }
+	}
+
+	/// Compress and write every pending frame
+	fn write_batch(&mut self) -> io::Result<()> {
+		let batch = std::mem::take(&mut self.batch);
+		let level = self.level;
+		let compressed = std::thread::scope(|s| {
+			let handles: Vec<_> = batch.iter()
+				.filter(|it| !it.is_empty())
+				.map(|it| s.spawn(move || zstd::bulk::compress(it, level)))
+				.collect();
+			handles.into_iter().map(|it| it.join().unwrap()).collect::<io::Result<Vec<_>>>()
+		})?;
+		for (frame, compressed) in batch.iter().zip(compressed) {
+			self.inner.write_all(&compressed)?;
+			self.frames.push((compressed.len() as u32, frame.len() as u32));
+		}
+		self.batch.push(batch.into_iter().next().map_or_else(Vec::new, |mut it| { it.clear(); it }));
+		Ok(())
+	}
+
+	/// Write remaining frames and seek table
+	pub(crate) fn finish(mut self) -> io::Result<W> {
+		self.write_batch()?;
+		let mut table = Vec::with_capacity(8 + self.frames.len() * 8 + FOOTER_SIZE);
+		table.extend(SKIPPABLE_MAGIC.to_le_bytes());
+		table.extend(((self.frames.len() * 8 + FOOTER_SIZE) as u32).to_le_bytes());
+		for (compressed, decompressed) in &self.frames {
+			table.extend(compressed.to_le_bytes());
+			table.extend(decompressed.to_le_bytes());
+		}
+		table.extend((self.frames.len() as u32).to_le_bytes());
+		// no checksum
+		table.push(0);
+		table.extend(SEEKABLE_MAGIC.to_le_bytes());
+		self.inner.write_all(&table)?;
+		self.inner.flush()?;
+		Ok(self.inner)
+	}
+}
+
+impl<W: Write> Write for SeekableWriter<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let frame = self.batch.last_mut().unwrap();
+		let len = buf.len().min(FRAME_SIZE - frame.len());
+		frame.extend_from_slice(&buf[..len]);
+		if frame.len() == FRAME_SIZE {
+			if self.batch.len() == self.jobs {
+				self.write_batch()?;
+			} else {
+				self.batch.push(Vec::with_capacity(FRAME_SIZE));
+			}
+		}
+		Ok(len)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+struct Frame {
+	compressed_offset: u64,
+	offset: u64,
+	compressed_size: u32,
+	size: u32,
+}
+
+/// Random access reader of seekable zstd file, only frames that contain requested range are decompressed
+pub(crate) struct SeekableReader {
+	file: Box<dyn ReadAt>,
+	frames: Vec<Frame>,
+	len: u64,
+	/// last decompressed frame, reads are mostly sequential
+	cache: Mutex<Option<(usize, Arc<Vec<u8>>)>>,
+}
+
+impl SeekableReader {
+	pub(crate) fn new(file: Box<dyn ReadAt>) -> io::Result<Self> {
+		let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a seekable zstd file");
+		let footer = file.read_at(file.len().checked_sub(FOOTER_SIZE as u64).ok_or_else(invalid)?, FOOTER_SIZE)?;
+		let u32_at = |buf: &[u8], pos: usize| u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap());
+		if u32_at(&footer, 5) != SEEKABLE_MAGIC { return Err(invalid()); }
+		let count = u32_at(&footer, 0) as usize;
+		let entry_size = if footer[4] & 0x80 != 0 { 12 } else { 8 };
+		let table_size = (count * entry_size + FOOTER_SIZE) as u64;
+		let table_start = file.len().checked_sub(table_size + 8).ok_or_else(invalid)?;
+		let table = file.read_at(table_start, table_size as usize + 8)?;
+		if u32_at(&table, 0) != SKIPPABLE_MAGIC { return Err(invalid()); }
+
+		let mut frames = Vec::with_capacity(count);
+		let (mut compressed_offset, mut offset) = (0, 0);
+		for i in 0..count {
+			let pos = 8 + i * entry_size;
+			let (compressed_size, size) = (u32_at(&table, pos), u32_at(&table, pos + 4));
+			frames.push(Frame { compressed_offset, offset, compressed_size, size });
+			compressed_offset += compressed_size as u64;
+			offset += size as u64;
+		}
+		Ok(Self { file, frames, len: offset, cache: Mutex::new(None) })
+	}
+
+	fn frame(&self, index: usize) -> io::Result<Arc<Vec<u8>>> {
+		if let Some((cached, content)) = &*self.cache.lock().unwrap() {
+			if *cached == index { return Ok(Arc::clone(content)); }
+		}
+		let frame = &self.frames[index];
+		let compressed = self.file.read_at(frame.compressed_offset, frame.compressed_size as usize)?;
+		let content = Arc::new(zstd::bulk::decompress(&compressed, frame.size as usize)?);
+		*self.cache.lock().unwrap() = Some((index, Arc::clone(&content)));
+		Ok(content)
+	}
+}
+
+impl ReadAt for SeekableReader {
+	fn len(&self) -> u64 {
+		self.len
+	}
+
+	fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+		let mut out = Vec::with_capacity(len);
+		let mut index = self.frames.partition_point(|it| it.offset + it.size as u64 <= offset);
+		let mut pos = offset;
+		while out.len() < len && index < self.frames.len() {
+			let frame = self.frame(index)?;
+			let start = (pos - self.frames[index].offset) as usize;
+			let end = frame.len().min(start + len - out.len());
+			out.extend_from_slice(&frame[start..end]);
+			pos += (end - start) as u64;
+			index += 1;
+		}
+		Ok(out)
+	}
+}
+
+/// [`Read`] and [`Seek`] over [`ReadAt`], so it can be walked by readers that expect a file
+pub(crate) struct Cursor<'a> {
+	source: &'a dyn ReadAt,
+	pos: u64,
+}
+
+impl<'a> Cursor<'a> {
+	pub(crate) fn new(source: &'a dyn ReadAt) -> Self {
+		Self { source, pos: 0 }
+	}
+}
+
+impl Read for Cursor<'_> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let content = self.source.read_at(self.pos, buf.len())?;
+		buf[..content.len()].copy_from_slice(&content);
+		self.pos += content.len() as u64;
+		Ok(content.len())
+	}
+}
+
+impl Seek for Cursor<'_> {
+	fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+		self.pos = match pos {
+			SeekFrom::Start(it) => it,
+			SeekFrom::End(it) => self.source.len().saturating_add_signed(it),
+			SeekFrom::Current(it) => self.pos.saturating_add_signed(it),
+		};
+		Ok(self.pos)
+	}
+}
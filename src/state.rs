@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use hyper::Method;
+use axum::http::Method;
 
 pub struct HttpState {
 	pub query: HashMap<String, String>,
@@ -1,63 +1,77 @@
-use std::path::{Component, Path, PathBuf};
+use std::path::{Path, PathBuf};
 
-use aho_corasick::AhoCorasick;
-use bstr::ByteSlice;
+use aho_corasick::{AhoCorasickBuilder, MatchKind};
 
-fn extend_absolute(buf: &[u8], rpath: &[u8], out: &mut Vec<u8>) {
-	/*let referrer = header.get(REFERER)
-		.map(|it| {
-			let mut b = it.as_bytes();
-			let mut pos = 1;
-			let end = b.len() - 1;
-			if b[pos - 1] == b'/' && b[pos] != b'/' {
-				//b = &b[1..];
-			} else {
-				while pos < end {
-					if b[pos - 1] != b'/' && b[pos] == b'/' && b[pos + 1] != b'/' {
-						b = &b[pos ..];
-						break;
-					}
-					pos += 1;
-				}
-			}
-			b
-		})
-		.unwrap_or_default();*/
-
-	//extend_absolute(x, referrer, &mut target);
+/// Attributes and css functions that may hold root-relative url
+static ROOT_RELATIVE: &[&str] = &["href=\"", "src=\"", "action=\"", "poster=\"", "href='", "src='", "action='", "url(", "url(\"", "url('"];
+/// Attributes that hold comma separated list of urls with descriptors
+static SRCSET: &[&str] = &["srcset=\"", "srcset='"];
 
-	let searcher = AhoCorasick::new(["href=\"/", "src=\"/"]);
+/// Point links of archived document at `base` (url or path without trailing slash),
+/// absolute links to any of `hosts` (eg. `http://localhost:3000`) and root-relative links are rewritten
+pub fn rebase_links(buf: &[u8], hosts: &[String], base: &str) -> Vec<u8> {
+	let searcher = AhoCorasickBuilder::new()
+		.match_kind(MatchKind::LeftmostLongest)
+		.build(hosts.iter().map(String::as_str).chain(ROOT_RELATIVE.iter().copied()).chain(SRCSET.iter().copied()));
+	let mut out = Vec::with_capacity(buf.len() + buf.len() / 16);
 	let mut off = 0;
-	let path = PathBuf::from(rpath.as_bstr().to_string());
-	let current = if rpath.ends_with(b"/") {
-		path
-	} else {
-		path.parent().unwrap_or(&path).to_path_buf()
-	};
-	let len = buf.len();
 	for x in searcher.find_iter(buf) {
-		let start = x.end();
-		out.extend(&buf[off..start - 1]);
-		let mut end = start;
-		while end < len {
-			if buf[end] == b'"' { break; }
-			end += 1;
+		// inside of srcset that was rewritten already
+		if x.start() < off { continue; }
+		let next = buf.get(x.end()).copied();
+		if x.pattern() < hosts.len() {
+			if !host_ends(next) { continue; }
+			out.extend(&buf[off..x.start()]);
+			out.extend(base.as_bytes());
+		} else if x.pattern() >= hosts.len() + ROOT_RELATIVE.len() {
+			let quote = buf[x.end() - 1];
+			let Some(len) = buf[x.end()..].iter().position(|it| *it == quote) else { continue };
+			out.extend(&buf[off..x.end()]);
+			rebase_srcset(&buf[x.end()..x.end() + len], hosts, base, &mut out);
+			off = x.end() + len;
+			continue;
+		} else {
+			// protocol relative url is not root-relative
+			if next != Some(b'/') || buf.get(x.end() + 1) == Some(&b'/') { continue; }
+			out.extend(&buf[off..x.end()]);
+			out.extend(base.as_bytes());
 		}
-		let path = &buf[start..end];
-		let mut res_pfx = vec![Component::CurDir];
-
-		//PathBuf::from(path.as_bstr().to_string()).strip_prefix(&current);
-		out.extend(PathBuf::from_iter(res_pfx).to_string_lossy().as_bytes());
-		off = end;
+		off = x.end();
 	}
 	out.extend(&buf[off..]);
-	/*if let Some(head) = chunks.next() {
-		out.extend(head);
+	out
+}
+
+/// Whether host matched at end of url, `localhost:3000` must not match `localhost:30001`
+fn host_ends(next: Option<u8>) -> bool {
+	next.is_none_or(|it| matches!(it, b'/' | b'"' | b'\'' | b'?' | b'#' | b')' | b'\\'))
+}
+
+/// Rebase every url of `srcset` value, candidates are url followed by optional descriptor and separated by commas
+fn rebase_srcset(value: &[u8], hosts: &[String], base: &str, out: &mut Vec<u8>) {
+	let mut pos = 0;
+	while pos < value.len() {
+		let start = pos + value[pos..].iter().take_while(|it| it.is_ascii_whitespace() || **it == b',').count();
+		out.extend(&value[pos..start]);
+		let end = start + value[start..].iter().take_while(|it| !it.is_ascii_whitespace()).count();
+		// commas that end url separate it from next candidate
+		let url = &value[start..end];
+		let url = &url[..url.len() - url.iter().rev().take_while(|it| **it == b',').count()];
+		let host = hosts.iter().find(|it| url.starts_with(it.as_bytes()) && host_ends(url.get(it.len()).copied()));
+		if let Some(host) = host {
+			out.extend(base.as_bytes());
+			out.extend(&url[host.len()..]);
+		} else {
+			if url.starts_with(b"/") && !url.starts_with(b"//") {
+				out.extend(base.as_bytes());
+			}
+			out.extend(url);
+		}
+		pos = if url.len() < end - start { start + url.len() } else {
+			value[end..].iter().position(|it| *it == b',').map_or(value.len(), |it| end + it)
+		};
+		out.extend(&value[start + url.len()..pos]);
 	}
-	for x in chunks {
-		out.extend(x);
-	}*/
-	// "href=\"/"
 }
 
 pub fn read_dir_recursive(path: &Path) -> Vec<(PathBuf, bool)> {
@@ -73,4 +87,48 @@ pub fn read_dir_recursive(path: &Path) -> Vec<(PathBuf, bool)> {
 		}
 	}
 	res
-}
\ No newline at end of file
+}
+
+/// Match `text` against pattern where `*` match any sequence and `?` match single character
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+	let (p, t) = (pattern.as_bytes(), text.as_bytes());
+	let (mut pi, mut ti) = (0, 0);
+	let mut star = None;
+	while ti < t.len() {
+		if pi < p.len() && (p[pi] == b'?' || p[pi] == t[ti]) {
+			pi += 1;
+			ti += 1;
+		} else if pi < p.len() && p[pi] == b'*' {
+			star = Some((pi, ti));
+			pi += 1;
+		} else if let Some((sp, st)) = star {
+			pi = sp + 1;
+			ti = st + 1;
+			star = Some((sp, st + 1));
+		} else {
+			return false;
+		}
+	}
+	p[pi..].iter().all(|it| *it == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+	use super::wildcard_match;
+
+	#[test]
+	fn wildcard() {
+		assert!(wildcard_match("*.html", "docs/page.html"));
+		assert!(wildcard_match("static/*", "static/app.js"));
+		assert!(wildcard_match("docs/*/page.html", "docs/a/b/page.html"));
+		assert!(wildcard_match("utm_*", "utm_"));
+		assert!(wildcard_match("*", ""));
+		assert!(wildcard_match("v?", "v1"));
+		assert!(wildcard_match("exact", "exact"));
+		assert!(!wildcard_match("*.html", "docs/page.css"));
+		assert!(!wildcard_match("static/*", "docs/static/app.js"));
+		assert!(!wildcard_match("docs/*/page.html", "docs/page.html"));
+		assert!(!wildcard_match("v?", "v10"));
+		assert!(!wildcard_match("exact", "exactly"));
+	}
+}
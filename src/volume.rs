@@ -0,0 +1,250 @@
+//! Archive split into volumes of fixed size, `<archive>.001`, `<archive>.002`... listed in order by `<archive>.volumes`
+
+use std::fs::File;
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+use std::fs::OpenOptions;
+use std::io;
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+use tracing::info;
+
+/// Extension of index of split archive, appended to name of archive
+pub(crate) const INDEX_EXT: &str = ".volumes";
+
+/// Content of index, size of every volume is kept so truncated or missing ones are noticed
+#[derive(Serialize, Deserialize)]
+struct Index {
+	volumes: Vec<Volume>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Volume {
+	/// file name relative to index
+	name: String,
+	size: u64,
+}
+
+/// Name of archive that `path` is index of, tell what format volumes are in
+pub(crate) fn archive_name(path: &Path) -> Option<String> {
+	path.to_string_lossy().strip_suffix(INDEX_EXT).map(str::to_string)
+}
+
+/// Split `path` into volumes of `size` bytes next to it and write their index, return path of index.
+/// Volumes are cut off the end of archive, so disk usage never exceed archive plus one volume
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+pub(crate) fn split(path: &Path, size: u64) -> io::Result<PathBuf> {
+	let with_suffix = |suffix: &str| {
+		let mut name = path.as_os_str().to_os_string();
+		name.push(suffix);
+		PathBuf::from(name)
+	};
+	let volume = |i: u64| with_suffix(&format!(".{:03}", i + 1));
+	let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+	let len = file.metadata()?.len();
+	let count = len.div_ceil(size).max(1);
+	let index = with_suffix(INDEX_EXT);
+	let res = (|| {
+		for i in (1..count).rev() {
+			let mut part = File::create(volume(i))?;
+			file.seek(SeekFrom::Start(i * size))?;
+			io::copy(&mut file, &mut part)?;
+			part.sync_all()?;
+			file.set_len(i * size)?;
+		}
+		file.sync_all()?;
+		std::fs::rename(path, volume(0))?;
+		let volumes = (0..count)
+			.map(|i| Volume {
+				name: volume(i).file_name().unwrap().to_string_lossy().into_owned(),
+				size: size.min(len - i * size),
+			})
+			.collect();
+		std::fs::write(&index, serde_json::to_vec_pretty(&Index { volumes }).unwrap())
+	})();
+	if let Err(e) = res {
+		// archive is cut as volumes are written, what's left of it is of no use
+		for i in 0..count {
+			let _ = std::fs::remove_file(volume(i));
+		}
+		let _ = std::fs::remove_file(&index);
+		return Err(e);
+	}
+	info!("split {path:?} into {count} volumes of {size} bytes");
+	Ok(index)
+}
+
+/// Volumes listed by index at `path` and their size, checked against files on disk
+pub(crate) fn open_volumes(path: &Path) -> io::Result<Vec<(File, u64)>> {
+	let index: Index = serde_json::from_slice(&std::fs::read(path)?)
+		.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{path:?}: {e}")))?;
+	if index.volumes.is_empty() {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{path:?} doesn't list any volume")));
+	}
+	let dir = path.parent().unwrap_or(Path::new(""));
+	index.volumes.iter()
+		.map(|it| {
+			let volume = dir.join(&it.name);
+			let file = File::open(&volume).map_err(|e| io::Error::new(e.kind(), format!("{volume:?}: {e}")))?;
+			let len = file.metadata()?.len();
+			if len != it.size {
+				return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{volume:?} is {len} bytes, index expect {}", it.size)));
+			}
+			Ok((file, it.size))
+		})
+		.collect()
+}
+
+/// Volumes read one after another as a single file
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+pub(crate) struct VolumeReader {
+	volumes: Vec<(File, u64)>,
+	/// offset of every volume
+	starts: Vec<u64>,
+	len: u64,
+	pos: u64,
+}
+
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+impl VolumeReader {
+	pub(crate) fn open(path: &Path) -> io::Result<Self> {
+		let volumes = open_volumes(path)?;
+		let mut starts = Vec::with_capacity(volumes.len());
+		let mut len = 0;
+		for (_, size) in &volumes {
+			starts.push(len);
+			len += size;
+		}
+		Ok(Self { volumes, starts, len, pos: 0 })
+	}
+}
+
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+impl Read for VolumeReader {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if self.pos >= self.len || buf.is_empty() {
+			return Ok(0);
+		}
+		let index = self.starts.partition_point(|&it| it <= self.pos) - 1;
+		let (file, size) = &mut self.volumes[index];
+		let offset = self.pos - self.starts[index];
+		// read stop at end of volume, entries that span volumes are continued by next read
+		let len = buf.len().min((*size - offset) as usize);
+		file.seek(SeekFrom::Start(offset))?;
+		let n = file.read(&mut buf[..len])?;
+		if n == 0 {
+			return Err(io::ErrorKind::UnexpectedEof.into());
+		}
+		self.pos += n as u64;
+		Ok(n)
+	}
+}
+
+#[cfg(any(feature = "zip", feature = "tar-archive"))]
+impl Seek for VolumeReader {
+	fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+		self.pos = match pos {
+			SeekFrom::Start(it) => it,
+			SeekFrom::End(it) => self.len.saturating_add_signed(it),
+			SeekFrom::Current(it) => self.pos.saturating_add_signed(it),
+		};
+		Ok(self.pos)
+	}
+}
+
+/// Zip content mapped into memory, piz read it as a single slice
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+pub(crate) enum Mapped {
+	File(memmap::Mmap),
+	#[cfg(unix)]
+	Volumes(VolumeMap),
+}
+
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+impl Mapped {
+	/// Map single file as is, several volumes side by side
+	pub(crate) fn new(files: &[(File, u64)]) -> io::Result<Self> {
+		match files {
+			[(file, _)] => Ok(Self::File(unsafe { memmap::Mmap::map(file)? })),
+			#[cfg(unix)]
+			_ => VolumeMap::new(files).map(Self::Volumes),
+			#[cfg(not(unix))]
+			_ => Err(io::Error::new(io::ErrorKind::Unsupported, "split zip can only be served on unix, extract it instead")),
+		}
+	}
+}
+
+#[cfg(all(feature = "serve-archive", feature = "piz"))]
+impl std::ops::Deref for Mapped {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		match self {
+			Mapped::File(it) => it,
+			#[cfg(unix)]
+			Mapped::Volumes(it) => it,
+		}
+	}
+}
+
+/// Volumes mapped next to each other into one reserved range of address space
+#[cfg(all(feature = "serve-archive", feature = "piz", unix))]
+pub(crate) struct VolumeMap {
+	ptr: *mut u8,
+	len: usize,
+}
+
+// mapping is read-only and owned
+#[cfg(all(feature = "serve-archive", feature = "piz", unix))]
+unsafe impl Send for VolumeMap {}
+#[cfg(all(feature = "serve-archive", feature = "piz", unix))]
+unsafe impl Sync for VolumeMap {}
+
+#[cfg(all(feature = "serve-archive", feature = "piz", unix))]
+impl VolumeMap {
+	fn new(files: &[(File, u64)]) -> io::Result<Self> {
+		use std::os::fd::AsRawFd;
+		let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+		if files[..files.len() - 1].iter().any(|(_, size)| size % page != 0) {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, format!("volumes aren't multiple of {page} bytes and can't be mapped")));
+		}
+		let len = files.iter().map(|(_, size)| *size as usize).sum::<usize>();
+		let ptr = unsafe { libc::mmap(std::ptr::null_mut(), len.max(1), libc::PROT_NONE, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0) };
+		if ptr == libc::MAP_FAILED {
+			return Err(io::Error::last_os_error());
+		}
+		// unmapped on drop, even when a volume fail to map
+		let map = Self { ptr: ptr.cast(), len };
+		let mut offset = 0;
+		for (file, size) in files.iter().filter(|(_, size)| *size > 0) {
+			let at = unsafe { map.ptr.add(offset) }.cast();
+			let mapped = unsafe { libc::mmap(at, *size as usize, libc::PROT_READ, libc::MAP_SHARED | libc::MAP_FIXED, file.as_raw_fd(), 0) };
+			if mapped == libc::MAP_FAILED {
+				return Err(io::Error::last_os_error());
+			}
+			offset += *size as usize;
+		}
+		Ok(map)
+	}
+}
+
+#[cfg(all(feature = "serve-archive", feature = "piz", unix))]
+impl std::ops::Deref for VolumeMap {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+	}
+}
+
+#[cfg(all(feature = "serve-archive", feature = "piz", unix))]
+impl Drop for VolumeMap {
+	fn drop(&mut self) {
+		unsafe { libc::munmap(self.ptr.cast(), self.len.max(1)) };
+	}
+}
@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use axum::http::Method;
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+
+use crate::archive::Entry;
+use crate::common::{archived_url, normalize_url_path};
+use crate::state::HttpState;
+
+/// Non-standard field with path of resource inside archive, so converting back doesn't depend on url mapping
+const PATH_FIELD: &str = "WARC-Archive-It-Path";
+
+/// Payload of `resource` or successful `response` record
+struct Record {
+	offset: u64,
+	len: u64,
+	modified: Option<SystemTime>,
+	/// start of gzip member that hold record, `offset` is then position inside of it once decompressed
+	#[cfg(feature = "flate2")]
+	member: Option<u64>,
+}
+
+/// Archive stored as WARC file, payloads are indexed once and read in place.
+/// Gzipped `.warc.gz` need `tar-archive` or `encrypt` feature, every record is decompressed from start of its member
+pub(crate) struct WarcSource {
+	file: Mutex<BufReader<File>>,
+	/// latest record of every storage key
+	records: BTreeMap<String, Record>,
+}
+
+impl WarcSource {
+	pub(crate) fn is_warc(path: &Path) -> bool {
+		let name = path.to_string_lossy();
+		#[cfg(feature = "flate2")]
+		let name = name.strip_suffix(".gz").unwrap_or(&name);
+		name.ends_with(".warc")
+	}
+
+	pub(crate) fn new(path: &Path) -> io::Result<Self> {
+		let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{path:?}: {msg}"));
+		let file = BufReader::new(File::open(path)?);
+		let mut records = BTreeMap::new();
+		#[cfg(feature = "flate2")]
+		if path.to_string_lossy().ends_with(".gz") {
+			let mut file = file;
+			// usually one member per record, but a whole file gzipped at once is read too
+			while !file.fill_buf()?.is_empty() {
+				let member = file.stream_position()?;
+				let mut reader = Counting { inner: BufReader::new(flate2::bufread::GzDecoder::new(&mut file)), pos: 0 };
+				while let Some((record, end)) = parse_record(&mut reader, invalid)? {
+					if let Some((key, record)) = record {
+						records.insert(key, Record { member: Some(member), ..record });
+					}
+					let rest = end.checked_sub(reader.pos).ok_or_else(|| invalid("http headers overflow record"))?;
+					io::copy(&mut (&mut reader).take(rest), &mut io::sink())?;
+				}
+			}
+			return Ok(Self { file: Mutex::new(file), records });
+		}
+		let mut reader = Counting { inner: file, pos: 0 };
+		while let Some((record, end)) = parse_record(&mut reader, invalid)? {
+			records.extend(record);
+			reader.inner.seek(SeekFrom::Start(end))?;
+			reader.pos = end;
+		}
+		Ok(Self { file: Mutex::new(reader.inner), records })
+	}
+
+	/// Every resource in file
+	pub(crate) fn entries(&self) -> Vec<Entry> {
+		self.records.iter()
+			.map(|(path, it)| Entry { path: path.clone(), size: it.len, modified: it.modified, link: None })
+			.collect()
+	}
+
+	pub(crate) fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+		let record = self.records.get(path).ok_or(io::ErrorKind::NotFound)?;
+		let mut buf = vec![0; record.len as usize];
+		let mut file = self.file.lock().unwrap();
+		#[cfg(feature = "flate2")]
+		if let Some(member) = record.member {
+			file.seek(SeekFrom::Start(member))?;
+			let mut reader = flate2::bufread::GzDecoder::new(&mut *file);
+			io::copy(&mut (&mut reader).take(record.offset), &mut io::sink())?;
+			reader.read_exact(&mut buf)?;
+			return Ok(buf);
+		}
+		file.seek(SeekFrom::Start(record.offset))?;
+		file.read_exact(&mut buf)?;
+		Ok(buf)
+	}
+}
+
+/// Reader that count bytes consumed from it, positions inside gzip member can't be asked to the file
+struct Counting<R> {
+	inner: R,
+	pos: u64,
+}
+
+impl<R: Read> Read for Counting<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let len = self.inner.read(buf)?;
+		self.pos += len as u64;
+		Ok(len)
+	}
+}
+
+impl<R: BufRead> BufRead for Counting<R> {
+	fn fill_buf(&mut self) -> io::Result<&[u8]> {
+		self.inner.fill_buf()
+	}
+
+	fn consume(&mut self, amt: usize) {
+		self.pos += amt as u64;
+		self.inner.consume(amt)
+	}
+}
+
+/// Storage key with payload of record that can be served, and position where block of record end
+type Parsed = (Option<(String, Record)>, u64);
+
+/// Read header of next record, and http headers of `response` one, so reader stop at its payload. `None` at end of file
+fn parse_record<R: BufRead>(reader: &mut Counting<R>, invalid: impl Fn(&str) -> io::Error) -> io::Result<Option<Parsed>> {
+	let mut line = String::new();
+	// records are separated by empty lines
+	while line.trim().is_empty() {
+		line.clear();
+		if reader.read_line(&mut line)? == 0 { return Ok(None); }
+	}
+	if !line.starts_with("WARC/") { return Err(invalid("not a warc file")); }
+	let fields = read_fields(reader)?;
+	let field = |name: &str| fields.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str());
+	let len: u64 = field("Content-Length").and_then(|it| it.parse().ok()).ok_or_else(|| invalid("record without Content-Length"))?;
+	let block = reader.pos;
+	let key = field(PATH_FIELD).map(str::to_string).or_else(|| field("WARC-Target-URI").and_then(storage_key));
+	let modified = field("WARC-Date")
+		.and_then(|it| chrono::DateTime::parse_from_rfc3339(it).ok())
+		.map(SystemTime::from);
+	let record = |offset: u64| Record {
+		offset,
+		len: block + len - offset,
+		modified,
+		#[cfg(feature = "flate2")]
+		member: None,
+	};
+	let record = match (field("WARC-Type"), key) {
+		(Some("resource"), Some(key)) => Some((key, record(block))),
+		(Some("response"), Some(key)) if len > 0 => {
+			// payload follow http status line and headers
+			line.clear();
+			reader.read_line(&mut line)?;
+			let ok = line.split_whitespace().nth(1).is_some_and(|it| it.starts_with('2'));
+			read_fields(reader)?;
+			let offset = reader.pos;
+			(ok && offset <= block + len).then(|| (key, record(offset)))
+		}
+		_ => None,
+	};
+	Ok(Some((record, block + len)))
+}
+
+/// Header fields up to empty line
+fn read_fields(reader: &mut impl BufRead) -> io::Result<Vec<(String, String)>> {
+	let mut fields = vec![];
+	let mut line = String::new();
+	loop {
+		line.clear();
+		if reader.read_line(&mut line)? == 0 { return Err(io::ErrorKind::UnexpectedEof.into()); }
+		let line = line.trim_end();
+		if line.is_empty() { return Ok(fields); }
+		if let Some((k, v)) = line.split_once(':') {
+			fields.push((k.trim().to_string(), v.trim().to_string()));
+		}
+	}
+}
+
+/// Path that forward would store resource of `uri` at
+fn storage_key(uri: &str) -> Option<String> {
+	// WARC 1.0 wrap uri in angle brackets
+	let url = Url::parse(uri.trim_start_matches('<').trim_end_matches('>')).ok()?;
+	let path = percent_decode_str(url.path().trim_start_matches('/')).decode_utf8_lossy().into_owned();
+	let state = HttpState {
+		query: url.query_pairs().into_owned().collect(),
+		method: Method::GET,
+	};
+	Some(normalize_url_path("".as_ref(), &state, &path, true).to_string_lossy().replace('\\', "/"))
+}
+
+/// Write archived files as `resource` records
+pub(crate) struct WarcWriter<W: Write> {
+	inner: W,
+	/// url that paths are resolved against
+	base: Url,
+}
+
+impl<W: Write> WarcWriter<W> {
+	pub(crate) fn new(inner: W, base: &str) -> io::Result<Self> {
+		let base = Url::parse(base).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{base}: {e}")))?;
+		let mut writer = Self { inner, base };
+		let info = format!("software: archive-it/{}\r\nformat: WARC File Format 1.1\r\n", env!("CARGO_PKG_VERSION"));
+		writer.record(&[("WARC-Type", "warcinfo"), ("Content-Type", "application/warc-fields")], None, info.as_bytes())?;
+		Ok(writer)
+	}
+
+	pub(crate) fn add(&mut self, path: &str, modified: Option<SystemTime>, content: &[u8]) -> io::Result<()> {
+		let url = archived_url(path);
+		let uri = self.base.join(url.trim_start_matches('/')).map_or_else(|_| format!("{}{}", self.base, &url[1..]), String::from);
+		let content_type = mime_guess::from_path(path).first().unwrap_or(mime_guess::mime::TEXT_HTML).to_string();
+		let fields = [("WARC-Type", "resource"), ("WARC-Target-URI", &uri), (PATH_FIELD, path), ("Content-Type", &content_type)];
+		self.record(&fields, modified, content)
+	}
+
+	fn record(&mut self, fields: &[(&str, &str)], date: Option<SystemTime>, block: &[u8]) -> io::Result<()> {
+		let date = chrono::DateTime::<chrono::Utc>::from(date.unwrap_or_else(SystemTime::now));
+		let mut id: [u8; 16] = rand::random();
+		// uuid version 4, variant 1
+		id[6] = id[6] & 0x0f | 0x40;
+		id[8] = id[8] & 0x3f | 0x80;
+		let id: String = id.iter().map(|it| format!("{it:02x}")).collect();
+		write!(self.inner, "WARC/1.1\r\nWARC-Record-ID: <urn:uuid:{}-{}-{}-{}-{}>\r\nWARC-Date: {}\r\n",
+			&id[..8], &id[8..12], &id[12..16], &id[16..20], &id[20..], date.format("%Y-%m-%dT%H:%M:%SZ"))?;
+		for (k, v) in fields {
+			write!(self.inner, "{k}: {v}\r\n")?;
+		}
+		write!(self.inner, "Content-Length: {}\r\n\r\n", block.len())?;
+		self.inner.write_all(block)?;
+		self.inner.write_all(b"\r\n\r\n")
+	}
+
+	pub(crate) fn finish(mut self) -> io::Result<W> {
+		self.inner.flush()?;
+		Ok(self.inner)
+	}
+}